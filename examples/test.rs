@@ -12,8 +12,9 @@ use ggez_3d::canvas::DrawParam3d;
 use ggez_3d::prelude::*;
 
 struct MainState {
+    renderer: Renderer3d,
     camera: CameraBundle,
-    meshes: Vec<(Mesh3d, Vec3, Vec3)>,
+    meshes: Vec<(MeshHandle, Vec3, Vec3)>,
     default_shader: bool,
     custom_shader: Shader,
 }
@@ -21,110 +22,24 @@ struct MainState {
 impl MainState {
     fn new(ctx: &mut Context) -> GameResult<Self> {
         let mut camera = CameraBundle::default();
-        let vertex_data = vec![
-            // top (0.0, 0.0, 1.0)
-            Vertex::new([-1.0, -1.0, 1.0], [0.0, 0.0], Color::GREEN),
-            Vertex::new([1.0, -1.0, 1.0], [1.0, 0.0], Color::GREEN),
-            Vertex::new([1.0, 1.0, 1.0], [1.0, 1.0], Color::GREEN),
-            Vertex::new([-1.0, 1.0, 1.0], [0.0, 1.0], Color::new(0.0, 1.0, 0.0, 0.5)),
-            // bottom (0.0, 0.0, -1.0)
-            Vertex::new([-1.0, 1.0, -1.0], [1.0, 0.0], None),
-            Vertex::new([1.0, 1.0, -1.0], [0.0, 0.0], None),
-            Vertex::new([1.0, -1.0, -1.0], [0.0, 1.0], None),
-            Vertex::new([-1.0, -1.0, -1.0], [1.0, 1.0], None),
-            // right (1.0, 0.0, 0.0)
-            Vertex::new([1.0, -1.0, -1.0], [0.0, 0.0], None),
-            Vertex::new([1.0, 1.0, -1.0], [1.0, 0.0], None),
-            Vertex::new([1.0, 1.0, 1.0], [1.0, 1.0], None),
-            Vertex::new([1.0, -1.0, 1.0], [0.0, 1.0], None),
-            // left (-1.0, 0.0, 0.0)
-            Vertex::new([-1.0, -1.0, 1.0], [1.0, 0.0], None),
-            Vertex::new([-1.0, 1.0, 1.0], [0.0, 0.0], None),
-            Vertex::new([-1.0, 1.0, -1.0], [0.0, 1.0], None),
-            Vertex::new([-1.0, -1.0, -1.0], [1.0, 1.0], None),
-            // front (0.0, 1.0, 0.0)
-            Vertex::new([1.0, 1.0, -1.0], [1.0, 0.0], None),
-            Vertex::new([-1.0, 1.0, -1.0], [0.0, 0.0], None),
-            Vertex::new([-1.0, 1.0, 1.0], [0.0, 1.0], None),
-            Vertex::new([1.0, 1.0, 1.0], [1.0, 1.0], None),
-            // back (0.0, -1.0, 0.0)
-            Vertex::new([1.0, -1.0, 1.0], [0.0, 0.0], None),
-            Vertex::new([-1.0, -1.0, 1.0], [1.0, 0.0], None),
-            Vertex::new([-1.0, -1.0, -1.0], [1.0, 1.0], None),
-            Vertex::new([1.0, -1.0, -1.0], [0.0, 1.0], None),
-        ];
-        let vertex_data_two = vec![
-            // top (0.0, 0.0, 1.0)
-            Vertex::new([2.0, 2.0, 2.0], [0.0, 0.0], None),
-            Vertex::new([4.0, 2.0, 2.0], [1.0, 0.0], None),
-            Vertex::new([4.0, 4.0, 2.0], [1.0, 1.0], None),
-            Vertex::new([2.0, 4.0, 2.0], [0.0, 1.0], None),
-            // bottom (0.0, 0.0, -1.0)
-            Vertex::new([2.0, 4.0, -1.0], [1.0, 0.0], None),
-            Vertex::new([4.0, 4.0, -1.0], [0.0, 0.0], None),
-            Vertex::new([4.0, 2.0, -1.0], [0.0, 1.0], None),
-            Vertex::new([2.0, 2.0, -1.0], [1.0, 1.0], None),
-            // right (1.0, 0.0, 0.0)
-            Vertex::new([4.0, 2.0, -1.0], [0.0, 0.0], None),
-            Vertex::new([4.0, 4.0, -1.0], [1.0, 0.0], None),
-            Vertex::new([4.0, 4.0, 2.0], [1.0, 1.0], None),
-            Vertex::new([4.0, 2.0, 2.0], [0.0, 1.0], None),
-            // left (-1.0, 0.0, 0.0)
-            Vertex::new([2.0, 2.0, 2.0], [1.0, 0.0], None),
-            Vertex::new([2.0, 4.0, 2.0], [0.0, 0.0], None),
-            Vertex::new([2.0, 4.0, -1.0], [0.0, 1.0], None),
-            Vertex::new([2.0, 2.0, -1.0], [1.0, 1.0], None),
-            // front (0.0, 1.0, 0.0)
-            Vertex::new([4.0, 4.0, -1.0], [1.0, 0.0], None),
-            Vertex::new([2.0, 4.0, -1.0], [0.0, 0.0], None),
-            Vertex::new([2.0, 4.0, 2.0], [0.0, 1.0], None),
-            Vertex::new([4.0, 4.0, 2.0], [1.0, 1.0], None),
-            // back (0.0, -1.0, 0.0)
-            Vertex::new([4.0, 2.0, 2.0], [0.0, 0.0], None),
-            Vertex::new([2.0, 2.0, 2.0], [1.0, 0.0], None),
-            Vertex::new([2.0, 2.0, -1.0], [1.0, 1.0], None),
-            Vertex::new([4.0, 2.0, -1.0], [0.0, 1.0], None),
-        ];
-
-        #[rustfmt::skip]
-        let index_data: Vec<u32> = vec![
-             0,  1,  2,  2,  3,  0, // top
-             4,  5,  6,  6,  7,  4, // bottom
-             8,  9, 10, 10, 11,  8, // right
-            12, 13, 14, 14, 15, 12, // left
-            16, 17, 18, 18, 19, 16, // front
-            20, 21, 22, 22, 23, 20, // back
-        ];
-
         let image_two =
             graphics::Image::from_color(ctx, 1, 1, Some(graphics::Color::from_rgb(50, 10, 50)));
-        let mut mesh = Mesh3d {
-            vertices: vertex_data,
-            indices: index_data.clone(),
-            vert_buffer: None,
-            ind_buffer: None,
-            bind_group: None,
-            texture: None,
-        };
-
-        mesh.gen_wgpu_buffer(ctx);
-
-        let mut mesh_two = Mesh3d {
-            vertices: vertex_data_two,
-            indices: index_data,
-            vert_buffer: None,
-            ind_buffer: None,
-            bind_group: None,
+        let mesh = Mesh3d::cube(2.0);
+        let mesh_two = Mesh3d {
             texture: Some(image_two),
+            ..Mesh3d::uv_sphere(1.5, 32, 16)
         };
 
-        mesh_two.gen_wgpu_buffer(ctx);
+        let mut renderer = Renderer3d::new(ctx);
+        let mesh = renderer.upload_mesh(ctx, &mesh);
+        let mesh_two = renderer.upload_mesh(ctx, &mesh_two);
         camera.camera.yaw = 90.0;
         Ok(MainState {
+            renderer,
             camera,
             meshes: vec![
                 (mesh, Vec3::new(10.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0)),
-                (mesh_two, Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.0, 0.0, 0.0)),
+                (mesh_two, Vec3::new(1.0, 1.0, 1.0), Vec3::new(3.0, 3.0, 0.5)),
             ],
             default_shader: true,
             custom_shader: graphics::ShaderBuilder::from_path("/fancy.wgsl")
@@ -184,18 +99,18 @@ impl event::EventHandler<ggez::GameError> for MainState {
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        let mut canvas3d = Canvas3d::from_frame(ctx, &mut self.camera);
+        let mut canvas3d = Canvas3d::from_frame(ctx, &mut self.renderer, &mut self.camera);
         if self.default_shader {
-            canvas3d.set_default_shader(ctx);
+            canvas3d.set_default_shader();
         } else {
             canvas3d.set_shader(self.custom_shader.clone());
         }
         for mesh in self.meshes.iter() {
             canvas3d.draw(
-                ctx,
-                mesh.0.clone(),
+                &mesh.0,
                 DrawParam3d::default()
                     .scale(mesh.1)
+                    .position(mesh.2)
                     .color(Color::new(0.5, 0.0, 0.0, 0.5)),
             );
         }
@@ -54,8 +54,35 @@ var t_color: texture_2d<f32>;
 @group(0) @binding(1)
 var s_sampler: sampler;
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+fn shade(in: VertexOutput) -> vec4<f32> {
     var tex = textureSample(t_color, s_sampler, in.tex_coord);
     return mix(mix(tex, vec4<f32>(in.color.xyz, 1.0), in.color.w), vec4<f32>(in.vertex_color.xyz, 1.0), in.vertex_color.w);
 }
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return shade(in);
+}
+
+// Order independent transparency convention, any shader with an `fs_oit` entry point
+// returning this struct can be drawn with `Canvas3d::set_oit`.
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) reveal: f32,
+}
+
+// Depth based weight from McGuire and Bavoil's weighted blended OIT,
+// closer fragments count for more in the average.
+fn oit_weight(depth: f32, alpha: f32) -> f32 {
+    return alpha * max(0.01, 3000.0 * pow(1.0 - depth, 3.0));
+}
+
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    let color = shade(in);
+    let weight = oit_weight(in.clip_position.z, color.a);
+    var out: OitOutput;
+    out.accum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.reveal = color.a;
+    return out;
+}
@@ -0,0 +1,22 @@
+// Composites the order independent transparency targets over the canvas target.
+
+@group(0) @binding(0)
+var t_accum: texture_2d<f32>;
+
+@group(0) @binding(1)
+var t_reveal: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    // A single triangle covering the whole target
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coord = vec2<i32>(position.xy);
+    let accum = textureLoad(t_accum, coord, 0);
+    let reveal = textureLoad(t_reveal, coord, 0).r;
+    return vec4<f32>(accum.rgb / max(accum.a, 0.00001), 1.0 - reveal);
+}
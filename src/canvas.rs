@@ -1,17 +1,24 @@
-use ggez::graphics::{Color, Shader};
+use ggez::graphics::{BlendMode, Color, Shader};
 use ggez::{glam::*, GameError, GameResult};
 use ggez::{graphics, Context};
-use wgpu::util::DeviceExt;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ops::Range;
+use std::sync::Arc;
 
 use crate::camera::CameraBundle;
-use crate::mesh::{Aabb, Instance3d, Transform3d, Vertex};
-use crate::{camera::CameraUniform, prelude::*};
+use crate::mesh::{Instance3d, InstanceBatch, MeshHandle, Transform3d};
+use crate::prelude::*;
+use crate::render::{PipelineKey3d, OIT_ACCUM_FORMAT, OIT_REVEAL_FORMAT};
 
 #[derive(Clone)]
 pub struct DrawParam3d {
     pub transform: Transform3d,
     /// The alpha component is used for intensity of blending instead of actual alpha
     pub color: Color,
+    /// Replaces the squared camera distance transparent draws are sorted by.
+    /// Higher keys are drawn first.
+    pub sort_key: Option<f32>,
 }
 
 impl DrawParam3d {
@@ -51,6 +58,11 @@ impl DrawParam3d {
         self.transform = transform;
         self
     }
+
+    pub fn sort_key(mut self, sort_key: f32) -> Self {
+        self.sort_key = Some(sort_key);
+        self
+    }
 }
 
 impl Default for DrawParam3d {
@@ -58,465 +70,793 @@ impl Default for DrawParam3d {
         Self {
             transform: Transform3d::default(),
             color: Color::new(1.0, 1.0, 1.0, 0.0),
+            sort_key: None,
         }
     }
 }
 
-#[derive(Clone)]
+/// How a draw is tested against and written to the depth image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DepthState3d {
+    /// When false every fragment passes, whatever `compare` is
+    pub test: bool,
+    pub write: bool,
+    pub compare: wgpu::CompareFunction,
+    /// Polygon offset, useful for decals
+    pub bias: wgpu::DepthBiasState,
+}
+
+impl Default for DepthState3d {
+    fn default() -> Self {
+        Self {
+            test: true,
+            write: true,
+            compare: wgpu::CompareFunction::Less,
+            bias: wgpu::DepthBiasState::default(),
+        }
+    }
+}
+
+impl DepthState3d {
+    pub fn test(mut self, test: bool) -> Self {
+        self.test = test;
+        self
+    }
+
+    pub fn write(mut self, write: bool) -> Self {
+        self.write = write;
+        self
+    }
+
+    pub fn compare(mut self, compare: wgpu::CompareFunction) -> Self {
+        self.compare = compare;
+        self
+    }
+
+    pub fn bias(mut self, constant: i32, slope_scale: f32) -> Self {
+        self.bias = wgpu::DepthBiasState {
+            constant,
+            slope_scale,
+            clamp: 0.0,
+        };
+        self
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct DrawState3d {
     pub shader: Shader,
+    pub depth: DepthState3d,
+    pub blend_mode: BlendMode,
+    /// Draw with weighted blended order independent transparency instead of `blend_mode`
+    pub oit: bool,
+    /// Faces to skip, `None` draws both sides
+    pub cull_mode: Option<wgpu::Face>,
+    /// Winding order of front facing triangles
+    pub front_face: wgpu::FrontFace,
+    /// `Line` and `Point` fall back to `Fill` on devices without the matching feature
+    pub polygon_mode: wgpu::PolygonMode,
 }
 
 #[derive(Clone)]
 pub struct DrawCommand3d {
-    pub mesh: Mesh3d, // Maybe take a reference instead
+    pub mesh: MeshHandle,
+    pub state: DrawState3d,
+    /// Range into [`Canvas3d::instances`], or into `instance_buffer` when that is set
+    pub instances: Range<u32>,
+    /// Buffer of an [`InstanceBatch`] to draw from instead of the per frame instances
+    pub instance_buffer: Option<Arc<wgpu::Buffer>>,
+    /// Mean transform of the [`InstanceBatch`] instances, what a transparent batch is sorted by
+    pub batch_transform: Mat4,
+}
+
+/// Draws that share a pipeline and mesh, rendered with a single instanced call
+pub struct DrawBatch3d {
+    pub mesh: MeshHandle,
     pub state: DrawState3d,
-    pub param: DrawParam3d,
+    /// Where this batch's instances live in the instance buffer
+    pub instances: Range<u32>,
+    /// Set when the instances come from an [`InstanceBatch`] rather than the renderer's buffer
+    pub instance_buffer: Option<Arc<wgpu::Buffer>>,
 }
 
-pub struct Canvas3d {
+/// What a transparent instance is sorted by, higher keys are drawn first.
+/// That is `sort_key` when there is one, otherwise the squared distance from `eye`
+/// to `center` moved by the instance's transform.
+fn transparent_sort_key(
+    eye: Vec3,
+    instance: &Instance3d,
+    center: Vec3,
+    sort_key: Option<f32>,
+) -> f32 {
+    sort_key.unwrap_or_else(|| {
+        let transform = Mat4::from_cols_array_2d(&instance.transform);
+        eye.distance_squared(transform.transform_point3(center))
+    })
+}
+
+/// Indices of `keys` grouped by equal keys, in order of first appearance.
+/// A `None` key never groups with anything and gets a group of its own.
+fn group_by_key<K: Eq + Hash>(keys: impl IntoIterator<Item = Option<K>>) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut lookup: HashMap<K, usize> = HashMap::new();
+    for (i, key) in keys.into_iter().enumerate() {
+        let Some(key) = key else {
+            groups.push(vec![i]);
+            continue;
+        };
+        match lookup.get(&key) {
+            Some(&group) => groups[group].push(i),
+            None => {
+                lookup.insert(key, groups.len());
+                groups.push(vec![i]);
+            }
+        }
+    }
+    groups
+}
+
+/// A single frame of 3D drawing, borrowing the long lived [`Renderer3d`]
+pub struct Canvas3d<'a> {
+    pub renderer: &'a mut Renderer3d,
     pub draws: Vec<DrawCommand3d>,
-    pub dirty_pipeline: bool,
+    /// Instances of every queued draw, in the order they were queued
+    pub instances: Vec<Instance3d>,
+    /// User sort key of each instance in `instances`
+    pub sort_keys: Vec<Option<f32>>,
+    /// Camera position at creation, transparent draws are sorted by distance to it
+    pub camera_position: Vec3,
+    /// Where this canvas's camera lives in the renderer's camera buffer
+    pub camera_offset: u32,
     pub state: DrawState3d,
-    pub original_state: DrawState3d,
-    pub pipeline: wgpu::RenderPipeline,
-    pub depth: graphics::ScreenImage,
-    pub camera_uniform: CameraUniform,
-    pub instance_buffer: wgpu::Buffer,
-    pub camera_buffer: wgpu::Buffer,
-    pub camera_bind_group: wgpu::BindGroup,
     pub target: graphics::Image,
+    /// Depth image to draw with, defaults to one from the renderer matching the target's size
+    pub depth: Option<graphics::Image>,
+    /// Value the depth image is cleared to, `None` keeps what earlier canvases drew
+    pub depth_clear: Option<f32>,
+    /// Samples per pixel, above 1 everything is drawn multisampled then resolved into the target
+    pub sample_count: u32,
 }
 
-impl Canvas3d {
-    pub fn from_frame(ctx: &mut Context, camera: &mut CameraBundle) -> Self {
-        Self::new(ctx, camera, ctx.gfx.frame().clone())
+impl<'a> Canvas3d<'a> {
+    pub fn from_frame(
+        ctx: &mut Context,
+        renderer: &'a mut Renderer3d,
+        camera: &mut CameraBundle,
+    ) -> Self {
+        let target = ctx.gfx.frame().clone();
+        Self::new(ctx, renderer, camera, target)
     }
 
     pub fn from_image(
         ctx: &mut Context,
+        renderer: &'a mut Renderer3d,
         camera: &mut CameraBundle,
         image: graphics::Image,
     ) -> Self {
-        Self::new(ctx, camera, image)
-    }
-
-    pub fn new(ctx: &mut Context, camera: &mut CameraBundle, target: graphics::Image) -> Self {
-        let cube_code = include_str!("../resources/cube.wgsl");
-        let shader = graphics::ShaderBuilder::from_code(cube_code)
-            .build(&ctx.gfx)
-            .unwrap(); // Should never fail since cube.wgsl is unchanging
-
-        camera.projection.aspect = ctx.gfx.size().0 / ctx.gfx.size().1;
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(camera);
-
-        let camera_buffer =
-            ctx.gfx
-                .wgpu()
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Camera Buffer"),
-                    contents: bytemuck::cast_slice(&[camera_uniform]),
-                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                });
+        Self::new(ctx, renderer, camera, image)
+    }
 
-        let instance_buffer =
-            ctx.gfx
-                .wgpu()
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Instance Buffer"),
-                    contents: bytemuck::cast_slice(&[Instance3d::default(), Instance3d::default()]),
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                });
+    pub fn new(
+        ctx: &mut Context,
+        renderer: &'a mut Renderer3d,
+        camera: &mut CameraBundle,
+        target: graphics::Image,
+    ) -> Self {
+        camera.projection.aspect = target.width() as f32 / target.height() as f32;
+        let camera_offset = renderer.update_camera(ctx, camera);
 
-        let camera_bind_group_layout =
-            ctx.gfx
-                .wgpu()
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }],
-                    label: Some("camera_bind_group_layout"),
-                });
-        let texture_bind_group_layout =
-            ctx.gfx
-                .wgpu()
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                multisampled: false,
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                    ],
-                    label: Some("texture_bind_group_layout"),
-                });
+        Canvas3d {
+            state: renderer.default_state(),
+            renderer,
+            draws: Vec::default(),
+            instances: Vec::default(),
+            sort_keys: Vec::default(),
+            camera_position: camera.camera.position,
+            camera_offset,
+            target,
+            depth: None,
+            depth_clear: Some(1.0),
+            sample_count: 1,
+        }
+    }
 
-        let camera_bind_group =
-            ctx.gfx
-                .wgpu()
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    layout: &camera_bind_group_layout,
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: camera_buffer.as_entire_binding(),
-                    }],
-                    label: Some("camera_bind_group"),
-                });
+    /// Draws with `depth` instead of the renderer's shared depth image.
+    /// It has to be a `Depth32Float` image the same size as the target.
+    pub fn set_depth(&mut self, depth: graphics::Image) {
+        self.depth = Some(depth);
+    }
 
-        let render_pipeline_layout =
-            ctx.gfx
-                .wgpu()
-                .device
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("Render Pipeline Layout"),
-                    bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
-                    push_constant_ranges: &[],
-                });
+    /// Clears depth to `clear` when finished, or keeps the existing depth if `None`.
+    /// Keeping it lets this canvas be depth tested against an earlier one.
+    pub fn set_depth_clear(&mut self, clear: Option<f32>) {
+        self.depth_clear = clear;
+    }
 
-        let depth = graphics::ScreenImage::new(ctx, graphics::ImageFormat::Depth32Float, 1., 1., 1);
-
-        let pipeline3d = Canvas3d {
-            depth,
-            dirty_pipeline: false,
-            camera_uniform,
-            camera_buffer,
-            camera_bind_group,
-            state: DrawState3d {
-                shader: shader.clone(),
-            },
-            original_state: DrawState3d {
-                shader: shader.clone(),
-            },
-            draws: Vec::default(),
-            pipeline: ctx.gfx.wgpu().device.create_render_pipeline(
-                &wgpu::RenderPipelineDescriptor {
-                    label: Some("Render Pipeline"),
-                    layout: Some(&render_pipeline_layout),
-                    vertex: wgpu::VertexState {
-                        module: shader.vs_module().unwrap(), // Should never fail since it's already built
-                        entry_point: "vs_main",
-                        buffers: &[Vertex::desc(), Instance3d::desc()],
-                    },
-                    primitive: wgpu::PrimitiveState {
-                        topology: wgpu::PrimitiveTopology::TriangleList,
-                        strip_index_format: None,
-                        front_face: wgpu::FrontFace::Ccw,
-                        cull_mode: Some(wgpu::Face::Back),
-                        unclipped_depth: false,
-                        polygon_mode: wgpu::PolygonMode::Fill,
-                        conservative: false,
-                    },
-                    depth_stencil: Some(wgpu::DepthStencilState {
-                        format: wgpu::TextureFormat::Depth32Float,
-                        depth_write_enabled: true,
-                        depth_compare: wgpu::CompareFunction::Less,
-                        stencil: wgpu::StencilState::default(),
-                        bias: wgpu::DepthBiasState::default(),
-                    }),
-                    multisample: wgpu::MultisampleState {
-                        count: 1,
-                        mask: !0,
-                        alpha_to_coverage_enabled: false,
-                    },
-                    fragment: Some(wgpu::FragmentState {
-                        module: shader.fs_module().unwrap(), // Should never fail since already built
-                        entry_point: "fs_main",
-                        targets: &[Some(wgpu::ColorTargetState {
-                            format: ctx.gfx.surface_format(),
-                            blend: Some(wgpu::BlendState {
-                                color: wgpu::BlendComponent::REPLACE,
-                                alpha: wgpu::BlendComponent::REPLACE,
-                            }),
-                            write_mask: wgpu::ColorWrites::ALL,
-                        })],
-                    }),
-                    multiview: None,
-                },
-            ),
-            instance_buffer,
-            target,
-        };
+    /// Smooths edges by drawing with `sample_count` samples per pixel, usually 4.
+    /// The samples live in a multisampled image shared by every canvas of this size,
+    /// so above 1 the canvas has to be finished with a clear color. Only counts
+    /// [`Renderer3d::sample_count_supported`] accepts can be finished.
+    pub fn set_sample_count(&mut self, sample_count: u32) {
+        self.sample_count = sample_count;
+    }
 
-        pipeline3d
+    /// Depth settings for the following draws
+    pub fn set_depth_state(&mut self, depth: DepthState3d) {
+        self.state.depth = depth;
     }
 
-    pub fn set_default_shader(&mut self, ctx: &mut Context) {
-        let cube_code = include_str!("../resources/cube.wgsl");
-        let shader = graphics::ShaderBuilder::from_code(cube_code)
-            .build(&ctx.gfx)
-            .unwrap(); // Should never fail since cube.wgsl is unchanging
-        self.state.shader = shader;
-        self.dirty_pipeline = true;
+    /// Blend mode for the following draws, the default [`BlendMode::REPLACE`] ignores alpha
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.state.blend_mode = blend_mode;
     }
 
+    /// Draws the following draws with weighted blended order independent transparency.
+    /// They are blended after everything else, in any order, and don't write depth.
+    /// The shader needs an `fs_oit` entry point, see [`Canvas3d::set_shader`].
+    pub fn set_oit(&mut self, oit: bool) {
+        self.state.oit = oit;
+    }
+
+    /// Faces culled by the following draws, `None` for double sided geometry
+    pub fn set_cull_mode(&mut self, cull_mode: Option<wgpu::Face>) {
+        self.state.cull_mode = cull_mode;
+    }
+
+    /// Winding order of front faces for the following draws, for meshes wound clockwise
+    pub fn set_front_face(&mut self, front_face: wgpu::FrontFace) {
+        self.state.front_face = front_face;
+    }
+
+    /// Polygon mode for the following draws, `Line` gives a wireframe.
+    /// See [`Renderer3d::polygon_mode_supported`] for whether the device can draw it.
+    pub fn set_polygon_mode(&mut self, polygon_mode: wgpu::PolygonMode) {
+        self.state.polygon_mode = polygon_mode;
+    }
+
+    pub fn set_default_shader(&mut self) {
+        self.state.shader = self.renderer.default_shader.clone();
+    }
+
+    /// Shader for the following draws, with the vertex and instance layout of `cube.wgsl`.
+    ///
+    /// It needs a `vs_main` vertex entry point and an `fs_main` fragment entry point.
+    /// Draws with [`Canvas3d::set_oit`] use `fs_oit` instead, which writes the weighted
+    /// premultiplied color, with the weight in alpha, to `@location(0)` and the alpha to
+    /// `@location(1)`. `cube.wgsl` shows how. [`Canvas3d::finish`] fails when an entry point
+    /// it needs is missing.
     pub fn set_shader(&mut self, shader: Shader) {
         self.state.shader = shader;
-        self.dirty_pipeline = true;
-    }
-
-    pub fn update_pipeline(&mut self, ctx: &mut Context) {
-        let camera_bind_group_layout =
-            ctx.gfx
-                .wgpu()
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }],
-                    label: Some("camera_bind_group_layout"),
-                });
-        let texture_bind_group_layout =
-            ctx.gfx
-                .wgpu()
-                .device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                multisampled: false,
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                            count: None,
-                        },
-                    ],
-                    label: Some("texture_bind_group_layout"),
-                });
-        let render_pipeline_layout =
-            ctx.gfx
-                .wgpu()
-                .device
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("Render Pipeline Layout"),
-                    bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
-                    push_constant_ranges: &[],
-                });
-
-        self.pipeline =
-            ctx.gfx
-                .wgpu()
-                .device
-                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("Render Pipeline"),
-                    layout: Some(&render_pipeline_layout),
-                    vertex: wgpu::VertexState {
-                        module: self.state.shader.vs_module().as_ref().unwrap_or(
-                            self.original_state.shader.vs_module().as_ref().unwrap_or(
-                                self.original_state.shader.vs_module().as_ref().unwrap(),
-                            ), // Should always exist
-                        ),
-                        entry_point: "vs_main",
-                        buffers: &[Vertex::desc(), Instance3d::desc()],
-                    },
-                    primitive: wgpu::PrimitiveState {
-                        topology: wgpu::PrimitiveTopology::TriangleList,
-                        strip_index_format: None,
-                        front_face: wgpu::FrontFace::Ccw,
-                        cull_mode: Some(wgpu::Face::Back),
-                        unclipped_depth: false,
-                        polygon_mode: wgpu::PolygonMode::Fill,
-                        conservative: false,
-                    },
-                    depth_stencil: Some(wgpu::DepthStencilState {
-                        format: wgpu::TextureFormat::Depth32Float,
-                        depth_write_enabled: true,
-                        depth_compare: wgpu::CompareFunction::Less,
-                        stencil: wgpu::StencilState::default(),
-                        bias: wgpu::DepthBiasState::default(),
-                    }),
-                    multisample: wgpu::MultisampleState {
-                        count: 1,
-                        mask: !0,
-                        alpha_to_coverage_enabled: false,
-                    },
-                    fragment: Some(wgpu::FragmentState {
-                        module: self
-                            .state
-                            .shader
-                            .fs_module()
-                            .as_ref()
-                            .unwrap_or(self.original_state.shader.fs_module().as_ref().unwrap()), // Should always exist since we use original
-                        entry_point: "fs_main",
-                        targets: &[Some(wgpu::ColorTargetState {
-                            format: ctx.gfx.surface_format(),
-                            blend: Some(wgpu::BlendState {
-                                color: wgpu::BlendComponent::REPLACE,
-                                alpha: wgpu::BlendComponent::REPLACE,
-                            }),
-                            write_mask: wgpu::ColorWrites::ALL,
-                        })],
-                    }),
-                    multiview: None,
-                });
     }
 
-    pub fn finish(&mut self, ctx: &mut Context, clear_color: Color) -> GameResult {
-        if self.dirty_pipeline {
-            self.update_pipeline(ctx);
+    /// Draws everything queued to the target.
+    /// The target is cleared to `clear_color` first, or drawn over if it is `None`,
+    /// which multisampled canvases can't do.
+    pub fn finish(
+        &mut self,
+        ctx: &mut Context,
+        clear_color: impl Into<Option<Color>>,
+    ) -> GameResult {
+        let clear_color = clear_color.into();
+        let sample_count = self.sample_count;
+        if sample_count > 1 && clear_color.is_none() {
+            return Err(GameError::RenderError(String::from(
+                "a multisampled canvas can't draw over its target, it needs a clear color",
+            )));
+        }
+        let mut formats = vec![self.target.format(), graphics::ImageFormat::Depth32Float];
+        if self.draws.iter().any(|draw| draw.state.oit) {
+            formats.extend([OIT_ACCUM_FORMAT, OIT_REVEAL_FORMAT]);
+        }
+        if let Some(format) = formats
+            .into_iter()
+            .find(|format| !Renderer3d::sample_count_supported(ctx, *format, sample_count))
+        {
+            return Err(GameError::RenderError(format!(
+                "{sample_count}x multisampling isn't supported for {format:?}"
+            )));
         }
 
-        self.update_instance_data(ctx);
-
-        let draws: Vec<DrawCommand3d> = self.draws.drain(..).collect();
+        // Anything that blends has to be drawn after the opaque geometry behind it
+        let mut opaque = Vec::new();
+        let mut transparent = Vec::new();
+        let mut oit = Vec::new();
+        for draw in self.draws.drain(..) {
+            if draw.state.oit {
+                oit.push(draw);
+            } else if draw.state.blend_mode == BlendMode::REPLACE {
+                opaque.push(draw);
+            } else {
+                transparent.push(draw);
+            }
+        }
+        let mut instance_data = Vec::with_capacity(self.instances.len());
+        let mut batches = Self::batch_draws(opaque, &self.instances, &mut instance_data);
+        batches.extend(Self::sort_transparent(
+            transparent,
+            &self.instances,
+            &self.sort_keys,
+            self.camera_position,
+            &mut instance_data,
+        ));
+        // Order doesn't matter for these, so they batch like opaque draws
+        let mut oit_batches = Self::batch_draws(oit, &self.instances, &mut instance_data);
+        self.instances.clear();
+        self.sort_keys.clear();
+        let first_instance = self.renderer.update_instance_data(ctx, &instance_data);
+        for batch in batches.iter_mut().chain(oit_batches.iter_mut()) {
+            if batch.instance_buffer.is_none() {
+                batch.instances.start += first_instance;
+                batch.instances.end += first_instance;
+            }
+        }
+        let pipeline_keys = self.pipeline_keys(ctx, &batches)?;
+        let oit_pipeline_keys = self.pipeline_keys(ctx, &oit_batches)?;
+        let oit_targets = if oit_batches.is_empty() {
+            None
+        } else {
+            self.renderer
+                .update_oit_resolve_pipeline(ctx, self.target.format());
+            Some(
+                self.renderer
+                    .oit_targets(ctx, self.target.width(), self.target.height()),
+            )
+        };
 
         {
-            let depth = self.depth.image(ctx);
-            let mut pass =
+            let depth = match &self.depth {
+                Some(depth) => {
+                    if depth.format() != graphics::ImageFormat::Depth32Float {
+                        return Err(GameError::RenderError(format!(
+                            "depth image is {:?} but has to be Depth32Float",
+                            depth.format()
+                        )));
+                    }
+                    if (depth.width(), depth.height())
+                        != (self.target.width(), self.target.height())
+                    {
+                        return Err(GameError::RenderError(format!(
+                            "depth image is {}x{} but the target is {}x{}",
+                            depth.width(),
+                            depth.height(),
+                            self.target.width(),
+                            self.target.height()
+                        )));
+                    }
+                    if depth.samples() != sample_count {
+                        return Err(GameError::RenderError(format!(
+                            "depth image has {} samples but the canvas draws with {sample_count}",
+                            depth.samples()
+                        )));
+                    }
+                    depth.clone()
+                }
+                None => self.renderer.depth_image(
+                    ctx,
+                    self.target.width(),
+                    self.target.height(),
+                    sample_count,
+                ),
+            };
+            let (width, height) = (self.target.width(), self.target.height());
+            let msaa = (sample_count > 1).then(|| {
+                self.renderer
+                    .msaa_image(ctx, self.target.format(), width, height, sample_count)
+            });
+            let oit_msaa = (sample_count > 1 && oit_targets.is_some()).then(|| {
+                (
+                    self.renderer
+                        .msaa_image(ctx, OIT_ACCUM_FORMAT, width, height, sample_count),
+                    self.renderer
+                        .msaa_image(ctx, OIT_REVEAL_FORMAT, width, height, sample_count),
+                )
+            });
+            let renderer = &*self.renderer;
+            let (view, resolve_target) = Self::color_views(&self.target, msaa.as_ref());
+            // Outside of a frame there is no ggez encoder, so record into one we submit ourselves
+            let mut standalone = ctx.gfx.commands().is_none().then(|| {
                 ctx.gfx
-                    .commands()
-                    .unwrap()
-                    .begin_render_pass(&wgpu::RenderPassDescriptor {
-                        label: None,
-                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                            view: self.target.wgpu().1,
-                            resolve_target: None,
+                    .wgpu()
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("Canvas3d"),
+                    })
+            });
+            let encoder = match standalone.as_mut() {
+                Some(encoder) => encoder,
+                None => ctx.gfx.commands().unwrap(),
+            };
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: match clear_color {
+                            Some(color) => {
+                                wgpu::LoadOp::Clear(graphics::LinearColor::from(color).into())
+                            }
+                            None => wgpu::LoadOp::Load,
+                        },
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth.wgpu().1,
+                    depth_ops: Some(wgpu::Operations {
+                        load: match self.depth_clear {
+                            Some(depth) => wgpu::LoadOp::Clear(depth),
+                            None => wgpu::LoadOp::Load,
+                        },
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            Self::draw_batches(
+                &mut pass,
+                renderer,
+                self.camera_offset,
+                &batches,
+                &pipeline_keys,
+            );
+            std::mem::drop(pass);
+
+            if let Some(oit_targets) = &oit_targets {
+                let (accum, accum_resolve) = Self::color_views(
+                    &oit_targets.accum,
+                    oit_msaa.as_ref().map(|(accum, _)| accum),
+                );
+                let (reveal, reveal_resolve) = Self::color_views(
+                    &oit_targets.reveal,
+                    oit_msaa.as_ref().map(|(_, reveal)| reveal),
+                );
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("oit_accumulate"),
+                    color_attachments: &[
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: accum,
+                            resolve_target: accum_resolve,
                             ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(
-                                    graphics::LinearColor::from(clear_color).into(),
-                                ),
+                                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
                                 store: true,
                             },
-                        })],
-                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                            view: depth.wgpu().1,
-                            depth_ops: Some(wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(1.0),
+                        }),
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: reveal,
+                            resolve_target: reveal_resolve,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
                                 store: true,
-                            }),
-                            stencil_ops: None,
+                            },
                         }),
-                    });
-            for (i, draw) in draws.iter().enumerate() {
-                let i = i as u32;
-                if draw.state.shader != self.state.shader {
-                    // self.set_shader(draw.state.shader.clone());
-                    // self.update_pipeline(ctx);
-                }
-
-                pass.set_pipeline(&self.pipeline);
-                pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-                pass.set_bind_group(
-                    0,
-                    draw.mesh.bind_group.as_ref().ok_or(GameError::CustomError(
-                        "Bind Group not generated for mesh".to_string(),
-                    ))?,
-                    &[],
-                );
-                pass.set_bind_group(1, &self.camera_bind_group, &[]);
-                pass.set_vertex_buffer(
-                    0,
-                    draw.mesh
-                        .vert_buffer
-                        .as_ref()
-                        .ok_or(GameError::CustomError(
-                            "Vert Buffer not generated for mesh".to_string(),
-                        ))?
-                        .slice(..),
-                );
-                pass.set_index_buffer(
-                    draw.mesh
-                        .ind_buffer
-                        .as_ref()
-                        .ok_or(GameError::CustomError(
-                            "Ind Buffer not generated for mesh".to_string(),
-                        ))?
-                        .slice(..),
-                    wgpu::IndexFormat::Uint32,
+                    ],
+                    // Tested against the opaque geometry drawn above
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: depth.wgpu().1,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+                Self::draw_batches(
+                    &mut pass,
+                    renderer,
+                    self.camera_offset,
+                    &oit_batches,
+                    &oit_pipeline_keys,
                 );
-                pass.draw_indexed(0..draw.mesh.indices.len() as u32, 0, i..i + 1);
+                std::mem::drop(pass);
+
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("oit_resolve"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: self.target.wgpu().1,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                pass.set_pipeline(renderer.oit_resolve_pipeline(self.target.format()));
+                pass.set_bind_group(0, &oit_targets.bind_group, &[]);
+                pass.draw(0..3, 0..1);
+                std::mem::drop(pass);
+            }
+
+            if let Some(encoder) = standalone {
+                ctx.gfx.wgpu().queue.submit([encoder.finish()]);
             }
-            std::mem::drop(pass);
         }
-        self.draws.clear();
         Ok(())
     }
 
-    pub fn update_instance_data(&mut self, ctx: &mut Context) {
-        let instance_data = self
-            .draws
+    /// Reads the target back as RGBA8 rows, see [`Renderer3d::read_pixels`]
+    pub fn read_pixels(&self, ctx: &mut Context) -> GameResult<Vec<u8>> {
+        Renderer3d::read_pixels(ctx, &self.target)
+    }
+
+    /// Reads back the depth this canvas drew with, see [`Renderer3d::read_depth`]
+    pub fn read_depth(&mut self, ctx: &mut Context) -> GameResult<Vec<f32>> {
+        let depth = match &self.depth {
+            Some(depth) => depth.clone(),
+            None => self.renderer.depth_image(
+                ctx,
+                self.target.width(),
+                self.target.height(),
+                self.sample_count,
+            ),
+        };
+        Renderer3d::read_depth(ctx, &depth)
+    }
+
+    /// The view to draw to and the one to resolve into, drawing to `msaa` when there is one
+    fn color_views<'i>(
+        image: &'i graphics::Image,
+        msaa: Option<&'i graphics::Image>,
+    ) -> (&'i wgpu::TextureView, Option<&'i wgpu::TextureView>) {
+        match msaa {
+            Some(msaa) => (msaa.wgpu().1, Some(image.wgpu().1)),
+            None => (image.wgpu().1, None),
+        }
+    }
+
+    /// Builds the pipeline of every batch, returning their keys in the same order
+    fn pipeline_keys(
+        &mut self,
+        ctx: &mut Context,
+        batches: &[DrawBatch3d],
+    ) -> GameResult<Vec<PipelineKey3d>> {
+        let keys = batches
             .iter()
-            .map(|x| {
-                Instance3d::from_param(&x.param, x.mesh.to_aabb().unwrap_or(Aabb::default()).center)
+            .map(|batch| PipelineKey3d {
+                state: batch.state.clone(),
+                format: self.target.format(),
+                topology: batch.mesh.topology,
+                sample_count: self.sample_count,
             })
             .collect::<Vec<_>>();
-        ctx.gfx.wgpu().queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&instance_data),
-        );
+        for key in keys.iter() {
+            self.renderer.update_pipeline(ctx, key)?;
+        }
+        Ok(keys)
     }
 
-    pub fn draw(&mut self, ctx: &mut Context, mesh: Mesh3d, param: DrawParam3d) {
-        let mut mesh = mesh;
-        mesh.gen_bind_group(&self.pipeline, ctx);
-        self.draws.push(DrawCommand3d {
-            mesh,
-            state: self.state.clone(),
-            param,
+    fn draw_batches<'p>(
+        pass: &mut wgpu::RenderPass<'p>,
+        renderer: &'p Renderer3d,
+        camera_offset: u32,
+        batches: &'p [DrawBatch3d],
+        pipeline_keys: &'p [PipelineKey3d],
+    ) {
+        pass.set_bind_group(1, &renderer.camera_bind_group, &[camera_offset]);
+        let mut current_key: Option<&PipelineKey3d> = None;
+        for (batch, key) in batches.iter().zip(pipeline_keys.iter()) {
+            let instance_buffer = batch
+                .instance_buffer
+                .as_deref()
+                .unwrap_or(&renderer.instance_buffer);
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            // Only switch pipelines when the state actually changes between batches
+            if current_key != Some(key) {
+                pass.set_pipeline(&renderer.pipelines[key]);
+                current_key = Some(key);
+            }
+
+            pass.set_bind_group(0, &batch.mesh.bind_group, &[]);
+            pass.set_vertex_buffer(0, batch.mesh.vert_buffer.slice(..));
+            pass.set_index_buffer(batch.mesh.ind_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..batch.mesh.index_count, 0, batch.instances.clone());
+        }
+    }
+
+    /// Groups draws by state and mesh, in order of first appearance, and lays out each
+    /// group's instances next to each other so it can be drawn with one call
+    fn batch_draws(
+        draws: Vec<DrawCommand3d>,
+        instances: &[Instance3d],
+        instance_data: &mut Vec<Instance3d>,
+    ) -> Vec<DrawBatch3d> {
+        // Instances already on the GPU are drawn straight from their own buffer
+        let groups = group_by_key(draws.iter().map(|draw| {
+            draw.instance_buffer
+                .is_none()
+                .then(|| (draw.state.clone(), draw.mesh.id()))
+        }));
+
+        let mut batches = Vec::with_capacity(groups.len());
+        for group in groups {
+            let draw = &draws[group[0]];
+            if draw.instance_buffer.is_some() {
+                batches.push(DrawBatch3d {
+                    mesh: draw.mesh.clone(),
+                    state: draw.state.clone(),
+                    instances: draw.instances.clone(),
+                    instance_buffer: draw.instance_buffer.clone(),
+                });
+                continue;
+            }
+            let start = instance_data.len() as u32;
+            for i in group {
+                let range = &draws[i].instances;
+                instance_data
+                    .extend_from_slice(&instances[range.start as usize..range.end as usize]);
+            }
+            batches.push(DrawBatch3d {
+                mesh: draw.mesh.clone(),
+                state: draw.state.clone(),
+                instances: start..instance_data.len() as u32,
+                instance_buffer: None,
+            });
+        }
+        batches
+    }
+
+    /// Sorts transparent instances back to front, by their key or the squared distance from `eye`
+    /// to their transformed bounds' center, then merges neighbours that share state and mesh.
+    /// Instances of an [`InstanceBatch`] live on the GPU, so a batch is sorted as a whole by
+    /// where its mean transform puts the center.
+    fn sort_transparent(
+        draws: Vec<DrawCommand3d>,
+        instances: &[Instance3d],
+        sort_keys: &[Option<f32>],
+        eye: Vec3,
+        instance_data: &mut Vec<Instance3d>,
+    ) -> Vec<DrawBatch3d> {
+        let mut items: Vec<(f32, (usize, Option<u32>))> = Vec::new();
+        for (i, draw) in draws.iter().enumerate() {
+            let center = Vec3::from(draw.mesh.aabb.center);
+            if draw.instance_buffer.is_some() {
+                let center = draw.batch_transform.transform_point3(center);
+                items.push((eye.distance_squared(center), (i, None)));
+                continue;
+            }
+            for instance in draw.instances.clone() {
+                let key = transparent_sort_key(
+                    eye,
+                    &instances[instance as usize],
+                    center,
+                    sort_keys[instance as usize],
+                );
+                items.push((key, (i, Some(instance))));
+            }
+        }
+        // Stable, so items with the same key keep the order they were queued in
+        items.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut batches: Vec<DrawBatch3d> = Vec::new();
+        for (_, (i, instance)) in items {
+            let draw = &draws[i];
+            let Some(instance) = instance else {
+                batches.push(DrawBatch3d {
+                    mesh: draw.mesh.clone(),
+                    state: draw.state.clone(),
+                    instances: draw.instances.clone(),
+                    instance_buffer: draw.instance_buffer.clone(),
+                });
+                continue;
+            };
+            instance_data.push(instances[instance as usize]);
+            let end = instance_data.len() as u32;
+            match batches.last_mut() {
+                Some(last)
+                    if last.instance_buffer.is_none()
+                        && last.instances.end + 1 == end
+                        && last.mesh.id() == draw.mesh.id()
+                        && last.state == draw.state =>
+                {
+                    last.instances.end = end;
+                }
+                _ => batches.push(DrawBatch3d {
+                    mesh: draw.mesh.clone(),
+                    state: draw.state.clone(),
+                    instances: end - 1..end,
+                    instance_buffer: None,
+                }),
+            }
+        }
+        batches
+    }
+
+    /// Queues `mesh` to be drawn with the current state when the canvas is finished
+    pub fn draw(&mut self, mesh: &MeshHandle, param: DrawParam3d) {
+        let instance = Instance3d::from_param(&param, mesh.aabb.center);
+        self.queue_instances(mesh, [(instance, param.sort_key)]);
+    }
+
+    /// Queues one instance of `mesh` per param, drawn with a single instanced call
+    pub fn draw_instanced(&mut self, mesh: &MeshHandle, params: &[DrawParam3d]) {
+        let instances = params.iter().map(|param| {
+            (
+                Instance3d::from_param(param, mesh.aabb.center),
+                param.sort_key,
+            )
         });
+        self.queue_instances(mesh, instances);
     }
 
-    pub fn resize(
+    /// Like [`Canvas3d::draw_instanced`] but with already built instance data
+    pub fn draw_instances(&mut self, mesh: &MeshHandle, instances: &[Instance3d]) {
+        self.queue_instances(mesh, instances.iter().map(|instance| (*instance, None)));
+    }
+
+    fn queue_instances(
         &mut self,
-        width: f32,
-        height: f32,
-        ctx: &mut Context,
-        camera: &mut CameraBundle,
+        mesh: &MeshHandle,
+        instances: impl IntoIterator<Item = (Instance3d, Option<f32>)>,
     ) {
-        camera.projection.resize(width as u32, height as u32);
-        self.camera_uniform.update_view_proj(camera);
-        ctx.gfx.wgpu().queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        );
+        let start = self.instances.len() as u32;
+        for (instance, sort_key) in instances {
+            self.instances.push(instance);
+            self.sort_keys.push(sort_key);
+        }
+        if start == self.instances.len() as u32 {
+            return;
+        }
+        self.draws.push(DrawCommand3d {
+            mesh: mesh.clone(),
+            state: self.state.clone(),
+            instances: start..self.instances.len() as u32,
+            instance_buffer: None,
+            batch_transform: Mat4::IDENTITY,
+        });
+    }
+
+    /// Queues `mesh` once per instance in `batch`, without uploading any instance data
+    pub fn draw_batch(&mut self, mesh: &MeshHandle, batch: &InstanceBatch) {
+        if batch.is_empty() {
+            return;
+        }
+        self.draws.push(DrawCommand3d {
+            mesh: mesh.clone(),
+            state: self.state.clone(),
+            instances: 0..batch.len(),
+            instance_buffer: Some(batch.buffer.clone()),
+            batch_transform: batch.mean_transform(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(position: [f32; 3]) -> Instance3d {
+        Instance3d::from_param(&DrawParam3d::default().position(position), Vec3::ZERO)
+    }
+
+    #[test]
+    fn sorts_far_to_near() {
+        let eye = Vec3::new(0.0, 0.0, 10.0);
+        let keys = [[0.0, 0.0, 5.0], [0.0, 0.0, -5.0], [3.0, 0.0, 0.0]]
+            .map(|position| transparent_sort_key(eye, &at(position), Vec3::ZERO, None));
+        assert!(keys[1] > keys[2] && keys[2] > keys[0]);
+        // Measured from the center moved by the transform
+        let centered = transparent_sort_key(eye, &at([0.0; 3]), Vec3::new(0.0, 0.0, 4.0), None);
+        assert_eq!(centered, 36.0);
     }
 
-    pub fn update_camera(&mut self, ctx: &mut Context, camera: &mut CameraBundle) {
-        self.camera_uniform.update_view_proj(camera);
-        ctx.gfx.wgpu().queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
+    #[test]
+    fn sort_key_overrides_distance() {
+        let far = at([0.0, 0.0, 9.0]);
+        assert_eq!(
+            transparent_sort_key(Vec3::ZERO, &far, Vec3::ZERO, None),
+            81.0
         );
+        assert_eq!(
+            transparent_sort_key(Vec3::ZERO, &far, Vec3::ZERO, Some(-1.0)),
+            -1.0
+        );
+    }
+
+    #[test]
+    fn groups_in_order_of_first_appearance() {
+        let groups = group_by_key([Some("b"), Some("a"), Some("b"), None, Some("a"), None]);
+        assert_eq!(groups, [vec![0, 2], vec![1, 4], vec![3], vec![5]]);
+        assert!(group_by_key::<u32>([]).is_empty());
     }
 }
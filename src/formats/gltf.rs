@@ -0,0 +1,751 @@
+//! glTF 2.0, both `.gltf` with embedded or external buffers and binary `.glb`.
+//!
+//! Meshes, the node hierarchy, base color textures and material factors are loaded.
+//! Animations, skins, morph targets and cameras are skipped, and files that require any
+//! extension are rejected. Meshes are written as `.glb` files holding a single node.
+
+use ::gltf::image::Source as ImageSource;
+use ::gltf::json::accessor::{ComponentType, Type};
+use ::gltf::json::buffer::Target;
+use ::gltf::json::{self, validation::Checked::Valid};
+use ::gltf::mesh::{Mode, Semantic};
+use ::gltf::texture::{MagFilter, MinFilter, WrappingMode};
+use base64::Engine;
+use ggez::graphics::{self, Image};
+use ggez::{Context, GameError, GameResult};
+use glam::Mat4;
+use std::collections::BTreeMap;
+
+use super::{in_file, read_bytes, resolve_path, write_bytes};
+use crate::mesh::{Mesh3d, Transform3d, Vertex};
+
+/// Material factors of a primitive, [`Mesh3d`] only carries its texture.
+/// Untextured primitives have the base color baked into their vertex colors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GltfMaterial {
+    pub name: Option<String>,
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    /// Should be drawn with culling off
+    pub double_sided: bool,
+    /// Should be drawn with a blend mode, or order independent transparency
+    pub blend: bool,
+    /// Index into [`GltfData::images`]
+    pub base_color_texture: Option<usize>,
+}
+
+impl Default for GltfMaterial {
+    fn default() -> Self {
+        Self {
+            name: None,
+            base_color: [1.0; 4],
+            metallic: 1.0,
+            roughness: 1.0,
+            emissive: [0.0; 3],
+            double_sided: false,
+            blend: false,
+            base_color_texture: None,
+        }
+    }
+}
+
+/// One primitive of a glTF mesh
+#[derive(Clone)]
+pub struct GltfPrimitive {
+    pub mesh: Mesh3d,
+    pub material: GltfMaterial,
+}
+
+#[derive(Clone)]
+pub struct GltfNode {
+    pub name: Option<String>,
+    /// Relative to the parent node
+    pub transform: Transform3d,
+    /// Index into [`GltfScene::meshes`]
+    pub mesh: Option<usize>,
+    /// Indices into [`GltfScene::nodes`]
+    pub children: Vec<usize>,
+}
+
+/// Where an image of a glTF file is
+#[derive(Clone, Debug, PartialEq)]
+pub enum GltfImage {
+    /// A file relative to the glTF file, with URI escapes undone
+    Uri(String),
+    /// Encoded image data from a buffer or a data URI
+    Bytes(Vec<u8>),
+}
+
+/// A parsed glTF file, before its images are loaded
+#[derive(Clone, Default)]
+pub struct GltfData {
+    /// Its meshes have no textures yet
+    pub scene: GltfScene,
+    pub images: Vec<GltfImage>,
+}
+
+/// Everything in a glTF file needed to draw its default scene
+#[derive(Clone, Default)]
+pub struct GltfScene {
+    /// Every mesh in the file, one [`Mesh3d`] per primitive
+    pub meshes: Vec<Vec<GltfPrimitive>>,
+    /// Every node in the file, in file order
+    pub nodes: Vec<GltfNode>,
+    /// Top level nodes of the default scene, or of the first one if none is marked default
+    pub roots: Vec<usize>,
+}
+
+impl GltfScene {
+    /// World matrix of every node, for [`Instance3d::new`](crate::mesh::Instance3d::new).
+    /// Nodes outside the drawn scene keep the identity.
+    pub fn world_transforms(&self) -> Vec<Mat4> {
+        let mut transforms = vec![Mat4::IDENTITY; self.nodes.len()];
+        let mut stack: Vec<(usize, Mat4)> = self
+            .roots
+            .iter()
+            .map(|&root| (root, Mat4::IDENTITY))
+            .collect();
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            let local = Mat4::from_scale_rotation_translation(
+                node.transform.scale.into(),
+                node.transform.rotation.into(),
+                node.transform.position.into(),
+            );
+            transforms[index] = parent * local;
+            stack.extend(
+                node.children
+                    .iter()
+                    .map(|&child| (child, transforms[index])),
+            );
+        }
+        transforms
+    }
+}
+
+/// Loads a `.gltf` or `.glb` file, with any external buffers and images next to it
+pub fn load(ctx: &mut Context, path: &str) -> GameResult<GltfScene> {
+    let bytes = read_bytes(ctx, path)?;
+    let data =
+        parse(&bytes, |uri| read_bytes(ctx, &resolve_path(path, uri))).map_err(in_file(path))?;
+
+    let mut images = Vec::with_capacity(data.images.len());
+    for (i, image) in data.images.iter().enumerate() {
+        let bytes = match image {
+            GltfImage::Uri(uri) => read_bytes(ctx, &resolve_path(path, uri))?,
+            GltfImage::Bytes(bytes) => bytes.clone(),
+        };
+        let image = Image::from_bytes(ctx, &bytes)
+            .map_err(|e| GameError::ResourceLoadError(format!("{path}: image {i}: {e}")))?;
+        images.push(image);
+    }
+
+    let mut scene = data.scene;
+    for primitive in scene.meshes.iter_mut().flatten() {
+        primitive.mesh.texture = primitive
+            .material
+            .base_color_texture
+            .map(|image| images[image].clone());
+    }
+    Ok(scene)
+}
+
+/// Parses `.gltf` or `.glb` data, calling `read` with the relative path of each external buffer
+pub fn parse(
+    bytes: &[u8],
+    mut read: impl FnMut(&str) -> GameResult<Vec<u8>>,
+) -> GameResult<GltfData> {
+    let error = GameError::ResourceLoadError;
+    let gltf =
+        ::gltf::Gltf::from_slice_without_validation(bytes).map_err(|e| error(e.to_string()))?;
+    // Checked before validating, which would only call the extension invalid
+    if let Some(extension) = gltf.document.extensions_required().next() {
+        return Err(error(format!(
+            "requires the unsupported extension `{extension}`"
+        )));
+    }
+    let blob = gltf.blob;
+    let document =
+        ::gltf::Document::from_json(gltf.document.into_json()).map_err(|e| error(e.to_string()))?;
+
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            ::gltf::buffer::Source::Bin => blob
+                .clone()
+                .ok_or_else(|| error(String::from("missing binary chunk")))?,
+            ::gltf::buffer::Source::Uri(uri) => match decode_data_uri(uri)? {
+                Some(data) => data,
+                None => read(&percent_decode(uri))?,
+            },
+        };
+        if data.len() < buffer.length() {
+            return Err(error(format!(
+                "buffer {} is {} bytes, expected {}",
+                buffer.index(),
+                data.len(),
+                buffer.length()
+            )));
+        }
+        buffers.push(data);
+    }
+
+    let mut images = Vec::new();
+    for image in document.images() {
+        let image = match image.source() {
+            ImageSource::View { view, .. } => {
+                let start = view.offset();
+                let data = buffers[view.buffer().index()]
+                    .get(start..start + view.length())
+                    .ok_or_else(|| error(format!("image {} is out of bounds", image.index())))?;
+                GltfImage::Bytes(data.to_vec())
+            }
+            ImageSource::Uri { uri, .. } => match decode_data_uri(uri)? {
+                Some(data) => GltfImage::Bytes(data),
+                None => GltfImage::Uri(percent_decode(uri)),
+            },
+        };
+        images.push(image);
+    }
+
+    let mut meshes = Vec::new();
+    for mesh in document.meshes() {
+        let mut primitives = Vec::new();
+        for primitive in mesh.primitives() {
+            primitives.push(load_primitive(&primitive, &buffers).map_err(|message| {
+                error(format!(
+                    "mesh {} primitive {}: {message}",
+                    mesh.index(),
+                    primitive.index()
+                ))
+            })?);
+        }
+        meshes.push(primitives);
+    }
+
+    let nodes = document
+        .nodes()
+        .map(|node| {
+            let (position, rotation, scale) = node.transform().decomposed();
+            GltfNode {
+                name: node.name().map(String::from),
+                transform: Transform3d {
+                    position: position.into(),
+                    rotation: rotation.into(),
+                    scale: scale.into(),
+                },
+                mesh: node.mesh().map(|mesh| mesh.index()),
+                children: node.children().map(|child| child.index()).collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+    if let Some(node) = find_cycle(&nodes) {
+        return Err(error(format!("node {node} is its own descendant")));
+    }
+    let roots = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .map(|scene| scene.nodes().map(|node| node.index()).collect())
+        .unwrap_or_default();
+
+    Ok(GltfData {
+        scene: GltfScene {
+            meshes,
+            nodes,
+            roots,
+        },
+        images,
+    })
+}
+
+/// Saves `mesh` as a `.glb` file in the user data directory, see [`write_glb`]
+pub fn save_glb(ctx: &Context, path: &str, mesh: &Mesh3d, texture: Option<&str>) -> GameResult {
+    write_bytes(ctx, path, &write_glb(mesh, texture)?)
+}
+
+/// Serializes `mesh` as binary glTF with one node, referring to `texture` as the base color
+/// texture by its path relative to the file. Vertex colors are only written for untextured
+/// meshes, and are read back with full coverage. Normals are left out if any of them is zero.
+pub fn write_glb(mesh: &Mesh3d, texture: Option<&str>) -> GameResult<Vec<u8>> {
+    if mesh.vertices.is_empty() || mesh.indices.is_empty() {
+        return Err(GameError::CustomError(String::from(
+            "glTF can't hold an empty mesh",
+        )));
+    }
+    if mesh
+        .vertices
+        .iter()
+        .any(|vertex| !vertex.pos.iter().all(|value| value.is_finite()))
+    {
+        return Err(GameError::CustomError(String::from(
+            "glTF can't hold a mesh with positions that aren't finite",
+        )));
+    }
+    let mode = match mesh.topology {
+        wgpu::PrimitiveTopology::PointList => Mode::Points,
+        wgpu::PrimitiveTopology::LineList => Mode::Lines,
+        wgpu::PrimitiveTopology::LineStrip => Mode::LineStrip,
+        wgpu::PrimitiveTopology::TriangleList => Mode::Triangles,
+        wgpu::PrimitiveTopology::TriangleStrip => Mode::TriangleStrip,
+    };
+    let default_color: [f32; 4] = graphics::Color::new(1.0, 1.0, 1.0, 0.0).into();
+    let colored = texture.is_none()
+        && mesh
+            .vertices
+            .iter()
+            .any(|vertex| vertex.color != default_color);
+    // A zero normal isn't a direction, readers would have to make one up for it
+    let with_normals = mesh.vertices.iter().all(|vertex| vertex.normal != [0.0; 3]);
+
+    let mut root = json::Root {
+        asset: json::Asset {
+            generator: Some(String::from("ggez_3d")),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    // Every attribute gets its own view, all of them 4 byte aligned
+    let mut bin = Vec::new();
+    let buffer = root.push(json::Buffer {
+        byte_length: 0_usize.into(),
+        name: None,
+        uri: None,
+        extensions: None,
+        extras: Default::default(),
+    });
+    let mut push = |root: &mut json::Root,
+                    data: Vec<u8>,
+                    target: Target,
+                    type_: Type,
+                    component_type: ComponentType,
+                    count: usize| {
+        let view = root.push(json::buffer::View {
+            buffer,
+            byte_length: data.len().into(),
+            byte_offset: Some(bin.len().into()),
+            byte_stride: None,
+            name: None,
+            target: Some(Valid(target)),
+            extensions: None,
+            extras: Default::default(),
+        });
+        bin.extend(data);
+        root.push(json::Accessor {
+            buffer_view: Some(view),
+            byte_offset: None,
+            count: count.into(),
+            component_type: Valid(json::accessor::GenericComponentType(component_type)),
+            extensions: None,
+            extras: Default::default(),
+            type_: Valid(type_),
+            min: None,
+            max: None,
+            name: None,
+            normalized: false,
+            sparse: None,
+        })
+    };
+    let count = mesh.vertices.len();
+    let vertex_data = |f: fn(&Vertex) -> &[f32]| -> Vec<u8> {
+        mesh.vertices
+            .iter()
+            .flat_map(|vertex| f(vertex).iter().flat_map(|value| value.to_le_bytes()))
+            .collect()
+    };
+    let mut attributes = BTreeMap::new();
+    let position = push(
+        &mut root,
+        vertex_data(|vertex| &vertex.pos),
+        Target::ArrayBuffer,
+        Type::Vec3,
+        ComponentType::F32,
+        count,
+    );
+    let (min, max) =
+        mesh.vertices
+            .iter()
+            .fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), vertex| {
+                (
+                    [0, 1, 2].map(|i| min[i].min(vertex.pos[i])),
+                    [0, 1, 2].map(|i| max[i].max(vertex.pos[i])),
+                )
+            });
+    let accessor = &mut root.accessors[position.value()];
+    accessor.min = Some(json::Value::from(min.to_vec()));
+    accessor.max = Some(json::Value::from(max.to_vec()));
+    attributes.insert(Valid(Semantic::Positions), position);
+    if with_normals {
+        let normal = push(
+            &mut root,
+            vertex_data(|vertex| &vertex.normal),
+            Target::ArrayBuffer,
+            Type::Vec3,
+            ComponentType::F32,
+            count,
+        );
+        attributes.insert(Valid(Semantic::Normals), normal);
+    }
+    let tex_coord = push(
+        &mut root,
+        vertex_data(|vertex| &vertex.tex_coord),
+        Target::ArrayBuffer,
+        Type::Vec2,
+        ComponentType::F32,
+        count,
+    );
+    attributes.insert(Valid(Semantic::TexCoords(0)), tex_coord);
+    if colored {
+        let color = push(
+            &mut root,
+            vertex_data(|vertex| &vertex.color),
+            Target::ArrayBuffer,
+            Type::Vec4,
+            ComponentType::F32,
+            count,
+        );
+        attributes.insert(Valid(Semantic::Colors(0)), color);
+    }
+    let indices = push(
+        &mut root,
+        mesh.indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes())
+            .collect(),
+        Target::ElementArrayBuffer,
+        Type::Scalar,
+        ComponentType::U32,
+        mesh.indices.len(),
+    );
+    root.buffers[buffer.value()].byte_length = bin.len().into();
+
+    let material = texture.map(|texture| {
+        let sampler = mesh.sampler;
+        let mag_filter = match sampler.mag {
+            graphics::FilterMode::Nearest => MagFilter::Nearest,
+            graphics::FilterMode::Linear => MagFilter::Linear,
+        };
+        let min_filter = match sampler.min {
+            graphics::FilterMode::Nearest => MinFilter::Nearest,
+            graphics::FilterMode::Linear => MinFilter::Linear,
+        };
+        let wrap = |clamp| match clamp {
+            graphics::ClampMode::Clamp => WrappingMode::ClampToEdge,
+            graphics::ClampMode::MirrorRepeat => WrappingMode::MirroredRepeat,
+            graphics::ClampMode::Repeat => WrappingMode::Repeat,
+        };
+        let sampler = root.push(json::texture::Sampler {
+            mag_filter: Some(Valid(mag_filter)),
+            min_filter: Some(Valid(min_filter)),
+            wrap_s: Valid(wrap(sampler.clamp_u)),
+            wrap_t: Valid(wrap(sampler.clamp_v)),
+            ..Default::default()
+        });
+        let source = root.push(json::Image {
+            buffer_view: None,
+            mime_type: None,
+            name: None,
+            uri: Some(percent_encode(texture)),
+            extensions: None,
+            extras: Default::default(),
+        });
+        let texture = root.push(json::Texture {
+            name: None,
+            sampler: Some(sampler),
+            source,
+            extensions: None,
+            extras: Default::default(),
+        });
+        root.push(json::Material {
+            pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+                base_color_texture: Some(json::texture::Info {
+                    index: texture,
+                    tex_coord: 0,
+                    extensions: None,
+                    extras: Default::default(),
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    });
+    let mesh = root.push(json::Mesh {
+        extensions: None,
+        extras: Default::default(),
+        name: None,
+        primitives: vec![json::mesh::Primitive {
+            attributes,
+            extensions: None,
+            extras: Default::default(),
+            indices: Some(indices),
+            material,
+            mode: Valid(mode),
+            targets: None,
+        }],
+        weights: None,
+    });
+    let node = root.push(json::Node {
+        mesh: Some(mesh),
+        ..Default::default()
+    });
+    let scene = root.push(json::Scene {
+        extensions: None,
+        extras: Default::default(),
+        name: None,
+        nodes: vec![node],
+    });
+    root.scene = Some(scene);
+
+    let json = root
+        .to_vec()
+        .map_err(|e| GameError::CustomError(e.to_string()))?;
+    let glb = ::gltf::Glb {
+        // The length is worked out while writing
+        header: ::gltf::binary::Header {
+            magic: *b"glTF",
+            version: 2,
+            length: 0,
+        },
+        json: json.into(),
+        bin: Some(bin.into()),
+    };
+    glb.to_vec()
+        .map_err(|e| GameError::CustomError(e.to_string()))
+}
+
+/// A node that can reach itself through its children, if there is one.
+/// glTF requires the nodes to form trees, anything else would be walked forever.
+fn find_cycle(nodes: &[GltfNode]) -> Option<usize> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Visit {
+        New,
+        /// Below this node, an ancestor of whatever is visited next
+        Open,
+        Done,
+    }
+    let mut visits = vec![Visit::New; nodes.len()];
+    for root in 0..nodes.len() {
+        if visits[root] != Visit::New {
+            continue;
+        }
+        // Each node along with how many of its children were visited
+        let mut stack = vec![(root, 0)];
+        visits[root] = Visit::Open;
+        while let Some((node, next)) = stack.last_mut() {
+            let Some(&child) = nodes[*node].children.get(*next) else {
+                visits[*node] = Visit::Done;
+                stack.pop();
+                continue;
+            };
+            *next += 1;
+            match visits[child] {
+                Visit::Open => return Some(child),
+                Visit::Done => {}
+                Visit::New => {
+                    visits[child] = Visit::Open;
+                    stack.push((child, 0));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn load_primitive(
+    primitive: &::gltf::Primitive,
+    buffers: &[Vec<u8>],
+) -> Result<GltfPrimitive, String> {
+    let material = primitive.material();
+    let pbr = material.pbr_metallic_roughness();
+    let base_color_texture = pbr.base_color_texture();
+    let tex_coord_set = base_color_texture
+        .as_ref()
+        .map_or(0, |info| info.tex_coord());
+
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+    let positions: Vec<[f32; 3]> = reader
+        .read_positions()
+        .ok_or("no POSITION attribute")?
+        .collect();
+    let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(Iterator::collect);
+    let tex_coords: Option<Vec<[f32; 2]>> = reader
+        .read_tex_coords(tex_coord_set)
+        .map(|tex_coords| tex_coords.into_f32().collect());
+    let colors: Option<Vec<[f32; 4]>> = reader
+        .read_colors(0)
+        .map(|colors| colors.into_rgba_f32().collect());
+    let mut indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    let counts = [
+        normals.as_ref().map(Vec::len),
+        tex_coords.as_ref().map(Vec::len),
+        colors.as_ref().map(Vec::len),
+    ];
+    if counts
+        .into_iter()
+        .flatten()
+        .any(|count| count != positions.len())
+    {
+        return Err(String::from("attributes have different vertex counts"));
+    }
+    if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+        return Err(format!(
+            "index {index} is out of range, there are {} vertices",
+            positions.len()
+        ));
+    }
+
+    let base_color = pbr.base_color_factor();
+    let vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(i, &position)| {
+            let uv = tex_coords.as_ref().map_or([0.0; 2], |uvs| uvs[i]);
+            // Vertex color alpha is how much it covers the texture, so keep it off textured meshes.
+            // Plain white is left as the default, which draws the same.
+            let tinted = colors.is_some() || base_color != [1.0; 4];
+            let color = (base_color_texture.is_none() && tinted).then(|| {
+                let color = colors.as_ref().map_or([1.0; 4], |colors| colors[i]);
+                graphics::Color::new(
+                    color[0] * base_color[0],
+                    color[1] * base_color[1],
+                    color[2] * base_color[2],
+                    1.0,
+                )
+            });
+            let vertex = Vertex::new(position, uv, color);
+            match &normals {
+                Some(normals) => vertex.normal(normals[i]),
+                None => vertex,
+            }
+        })
+        .collect();
+
+    let topology = match primitive.mode() {
+        Mode::Points => wgpu::PrimitiveTopology::PointList,
+        Mode::Lines => wgpu::PrimitiveTopology::LineList,
+        Mode::LineStrip => wgpu::PrimitiveTopology::LineStrip,
+        Mode::Triangles => wgpu::PrimitiveTopology::TriangleList,
+        Mode::TriangleStrip => wgpu::PrimitiveTopology::TriangleStrip,
+        // wgpu has no loops or fans, so they become a strip and a list
+        Mode::LineLoop => {
+            if let Some(&first) = indices.first() {
+                indices.push(first);
+            }
+            wgpu::PrimitiveTopology::LineStrip
+        }
+        Mode::TriangleFan => {
+            indices = (1..indices.len().saturating_sub(1))
+                .flat_map(|i| [indices[0], indices[i], indices[i + 1]])
+                .collect();
+            wgpu::PrimitiveTopology::TriangleList
+        }
+    };
+
+    let (image, sampler) = match &base_color_texture {
+        Some(info) => {
+            let texture = info.texture();
+            (Some(texture.source().index()), sampler(&texture.sampler()))
+        }
+        None => (None, graphics::Sampler::default()),
+    };
+
+    Ok(GltfPrimitive {
+        mesh: Mesh3d {
+            vertices,
+            indices,
+            sampler,
+            topology,
+            ..Default::default()
+        },
+        material: GltfMaterial {
+            name: material.name().map(String::from),
+            base_color,
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            emissive: material.emissive_factor(),
+            double_sided: material.double_sided(),
+            blend: material.alpha_mode() == ::gltf::material::AlphaMode::Blend,
+            base_color_texture: image,
+        },
+    })
+}
+
+fn sampler(sampler: &::gltf::texture::Sampler) -> graphics::Sampler {
+    let clamp = |mode| match mode {
+        WrappingMode::ClampToEdge => graphics::ClampMode::Clamp,
+        WrappingMode::MirroredRepeat => graphics::ClampMode::MirrorRepeat,
+        WrappingMode::Repeat => graphics::ClampMode::Repeat,
+    };
+    let mag = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => graphics::FilterMode::Nearest,
+        _ => graphics::FilterMode::Linear,
+    };
+    let min = match sampler.min_filter() {
+        Some(
+            MinFilter::Nearest | MinFilter::NearestMipmapNearest | MinFilter::NearestMipmapLinear,
+        ) => graphics::FilterMode::Nearest,
+        _ => graphics::FilterMode::Linear,
+    };
+    graphics::Sampler {
+        clamp_u: clamp(sampler.wrap_s()),
+        clamp_v: clamp(sampler.wrap_t()),
+        clamp_w: graphics::ClampMode::Clamp,
+        mag,
+        min,
+    }
+}
+
+/// Decodes a base64 data URI, `None` for anything else
+fn decode_data_uri(uri: &str) -> GameResult<Option<Vec<u8>>> {
+    let Some(data) = uri.strip_prefix("data:") else {
+        return Ok(None);
+    };
+    let (_, data) = data
+        .split_once(";base64,")
+        .ok_or_else(|| GameError::ResourceLoadError(String::from("unsupported data URI")))?;
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map(Some)
+        .map_err(|e| GameError::ResourceLoadError(e.to_string()))
+}
+
+/// Escapes everything but unreserved characters and `/` the way [`percent_decode`] undoes
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Undoes the `%20` style escapes URIs use for spaces and other characters
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| uri.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,57 @@
+//! Loading meshes from common file formats through ggez's [`Filesystem`](ggez::filesystem),
+//! so paths are resolved against the resource paths like any other ggez resource.
+//! Meshes are saved where [`Filesystem::create`](ggez::filesystem::Filesystem::create) puts
+//! files, the user data directory.
+
+use ggez::{Context, GameError, GameResult};
+use std::io::{Read, Write};
+
+pub mod gltf;
+pub mod obj;
+pub mod ply;
+pub mod stl;
+
+/// Reads the whole file at `path`
+pub(crate) fn read_bytes(ctx: &Context, path: &str) -> GameResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    ctx.fs.open(path)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Writes `bytes` to `path` in the user data directory, replacing any file there
+pub(crate) fn write_bytes(ctx: &Context, path: &str, bytes: &[u8]) -> GameResult {
+    ctx.fs.create(path)?.write_all(bytes)?;
+    Ok(())
+}
+
+pub(crate) fn read_string(ctx: &Context, path: &str) -> GameResult<String> {
+    String::from_utf8(read_bytes(ctx, path)?)
+        .map_err(|_| GameError::ResourceLoadError(format!("{path}: not valid UTF-8")))
+}
+
+/// Resolves `relative`, as written in the file at `base`, to a filesystem path
+pub(crate) fn resolve_path(base: &str, relative: &str) -> String {
+    let relative = relative.replace('\\', "/");
+    if relative.starts_with('/') {
+        return relative;
+    }
+    match base.rfind('/') {
+        Some(end) => format!("{}/{relative}", &base[..end]),
+        None => format!("/{relative}"),
+    }
+}
+
+/// Error for something wrong on line `line`, counting from 1
+pub(crate) fn line_error(line: usize, message: impl std::fmt::Display) -> GameError {
+    GameError::ResourceLoadError(format!("line {line}: {message}"))
+}
+
+/// Prefixes load errors from parsing with the file they came from
+pub(crate) fn in_file(path: &str) -> impl Fn(GameError) -> GameError + '_ {
+    move |e| match e {
+        GameError::ResourceLoadError(message) => {
+            GameError::ResourceLoadError(format!("{path}: {message}"))
+        }
+        e => e,
+    }
+}
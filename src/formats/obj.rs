@@ -0,0 +1,436 @@
+//! Wavefront OBJ with MTL materials.
+//!
+//! Faces are triangulated as fans, so concave polygons should be triangulated on export.
+//! Points, lines and free-form geometry are skipped.
+//! Meshes are written with one vertex, texture coordinate and normal per [`Vertex`].
+
+use ggez::graphics::{self, Image};
+use ggez::{Context, GameError, GameResult};
+use std::collections::HashMap;
+use std::str::SplitWhitespace;
+
+use super::{in_file, line_error, read_string, resolve_path, write_bytes};
+use crate::mesh::{Mesh3d, Vertex};
+
+/// Name of the material [`write`] and [`write_mtl`] use
+const MATERIAL: &str = "textured";
+
+/// Zero based position, texture coordinate and normal indices of a face's corner
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+/// Triangles of an OBJ file that share a material
+#[derive(Clone, Default)]
+pub struct ObjGroup {
+    /// Name given to `usemtl`, `None` for faces before the first one
+    pub material: Option<String>,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+/// A parsed OBJ file, before its materials are loaded
+#[derive(Clone, Default)]
+pub struct ObjData {
+    /// In the order their materials were first used
+    pub groups: Vec<ObjGroup>,
+    /// Material files named by `mtllib`, relative to the OBJ file
+    pub material_libs: Vec<String>,
+}
+
+/// The parts of an MTL material that map onto a [`Mesh3d`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjMaterial {
+    pub name: String,
+    /// `Kd`, used as the vertex color when there is no texture
+    pub diffuse: [f32; 3],
+    /// `map_Kd`, relative to the MTL file
+    pub diffuse_texture: Option<String>,
+}
+
+impl Default for ObjMaterial {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            diffuse: [1.0; 3],
+            diffuse_texture: None,
+        }
+    }
+}
+
+/// Loads the OBJ file at `path` with its materials, one [`Mesh3d`] per material.
+/// Materials with a `map_Kd` get it as their texture, others color their vertices `Kd`.
+/// Vertex colors would cover the texture, so textured meshes drop them, as glTF meshes do.
+pub fn load(ctx: &mut Context, path: &str) -> GameResult<Vec<Mesh3d>> {
+    let data = parse(&read_string(ctx, path)?).map_err(in_file(path))?;
+
+    let mut materials = HashMap::new();
+    for lib in data.material_libs.iter() {
+        let lib_path = resolve_path(path, lib);
+        let source = read_string(ctx, &lib_path)?;
+        for mut material in parse_mtl(&source).map_err(in_file(&lib_path))? {
+            material.diffuse_texture = material
+                .diffuse_texture
+                .map(|texture| resolve_path(&lib_path, &texture));
+            materials.insert(material.name.clone(), material);
+        }
+    }
+
+    let mut textures: HashMap<String, Image> = HashMap::new();
+    let mut meshes = Vec::with_capacity(data.groups.len());
+    for mut group in data.groups {
+        let material = group.material.as_ref().and_then(|name| materials.get(name));
+        let mut texture = None;
+        if let Some(material) = material {
+            match &material.diffuse_texture {
+                Some(texture_path) => {
+                    if !textures.contains_key(texture_path) {
+                        let image = Image::from_path(ctx, texture_path)?;
+                        textures.insert(texture_path.clone(), image);
+                    }
+                    texture = textures.get(texture_path).cloned();
+                    let untinted = Vertex::new([0.0; 3], [0.0; 2], None).color;
+                    for vertex in group.vertices.iter_mut() {
+                        vertex.color = untinted;
+                    }
+                }
+                None => {
+                    let [r, g, b] = material.diffuse;
+                    let color = graphics::Color::new(r, g, b, 1.0);
+                    for vertex in group.vertices.iter_mut() {
+                        vertex.color = color.into();
+                    }
+                }
+            }
+        }
+        meshes.push(Mesh3d {
+            vertices: group.vertices,
+            indices: group.indices,
+            texture,
+            ..Default::default()
+        });
+    }
+    Ok(meshes)
+}
+
+/// Parses OBJ source into triangles grouped by material.
+/// Vertices are shared between faces that use the same position, uv and normal.
+pub fn parse(source: &str) -> GameResult<ObjData> {
+    let mut positions: Vec<([f32; 3], Option<[f32; 3]>)> = Vec::new();
+    let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut data = ObjData::default();
+    let mut group_lookup: HashMap<Option<String>, usize> = HashMap::new();
+    let mut current = None;
+    let mut vertex_lookup: Vec<HashMap<FaceVertex, u32>> = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        match keyword {
+            "v" => {
+                let values = floats(tokens, line_number)?;
+                match values.len() {
+                    // Some exporters append a vertex color
+                    6 => positions.push((
+                        [values[0], values[1], values[2]],
+                        Some([values[3], values[4], values[5]]),
+                    )),
+                    3 | 4 => positions.push(([values[0], values[1], values[2]], None)),
+                    n => return Err(line_error(line_number, format!("vertex has {n} values"))),
+                }
+            }
+            "vt" => {
+                let values = floats(tokens, line_number)?;
+                if values.is_empty() || values.len() > 3 {
+                    return Err(line_error(
+                        line_number,
+                        format!("texture coordinate has {} values", values.len()),
+                    ));
+                }
+                // OBJ puts v = 0 at the bottom of the image
+                tex_coords.push([values[0], 1.0 - values.get(1).copied().unwrap_or(0.0)]);
+            }
+            "vn" => {
+                let values = floats(tokens, line_number)?;
+                if values.len() != 3 {
+                    return Err(line_error(
+                        line_number,
+                        format!("normal has {} values", values.len()),
+                    ));
+                }
+                normals.push([values[0], values[1], values[2]]);
+            }
+            "f" => {
+                let group = *current.get_or_insert_with(|| {
+                    group_index(&mut data, &mut group_lookup, &mut vertex_lookup, None)
+                });
+                let mut face = Vec::new();
+                for token in tokens {
+                    let key = face_vertex(
+                        token,
+                        (positions.len(), tex_coords.len(), normals.len()),
+                        line_number,
+                    )?;
+                    let group_data = &mut data.groups[group];
+                    let index = *vertex_lookup[group].entry(key).or_insert_with(|| {
+                        let (position, color) = positions[key.0];
+                        let uv = key.1.map_or([0.0, 0.0], |i| tex_coords[i]);
+                        let mut vertex = Vertex::new(
+                            position,
+                            uv,
+                            color.map(|[r, g, b]| graphics::Color::new(r, g, b, 1.0)),
+                        );
+                        if let Some(normal) = key.2 {
+                            vertex = vertex.normal(normals[normal]);
+                        }
+                        group_data.vertices.push(vertex);
+                        group_data.vertices.len() as u32 - 1
+                    });
+                    face.push(index);
+                }
+                if face.len() < 3 {
+                    return Err(line_error(
+                        line_number,
+                        format!("face has {} vertices", face.len()),
+                    ));
+                }
+                let indices = &mut data.groups[group].indices;
+                for i in 1..face.len() - 1 {
+                    indices.extend([face[0], face[i], face[i + 1]]);
+                }
+            }
+            "usemtl" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                current = Some(group_index(
+                    &mut data,
+                    &mut group_lookup,
+                    &mut vertex_lookup,
+                    Some(name),
+                ));
+            }
+            "mtllib" => data.material_libs.extend(tokens.map(|lib| lib.to_string())),
+            // Objects, groups, smoothing, points, lines and free-form geometry are skipped
+            _ => {}
+        }
+    }
+    // Groups switched to but never given a face are left out
+    data.groups.retain(|group| !group.indices.is_empty());
+    Ok(data)
+}
+
+/// Parses MTL source, keeping the parts [`load`] uses
+pub fn parse_mtl(source: &str) -> GameResult<Vec<ObjMaterial>> {
+    let mut materials: Vec<ObjMaterial> = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        if keyword == "newmtl" {
+            materials.push(ObjMaterial {
+                name: tokens.collect::<Vec<_>>().join(" "),
+                ..Default::default()
+            });
+            continue;
+        }
+        let Some(material) = materials.last_mut() else {
+            return Err(line_error(
+                line_number,
+                format!("`{keyword}` before any `newmtl`"),
+            ));
+        };
+        match keyword {
+            "Kd" => {
+                let values = floats(tokens, line_number)?;
+                material.diffuse = match values[..] {
+                    [r, g, b] => [r, g, b],
+                    [v] => [v; 3],
+                    _ => return Err(line_error(line_number, "expected an RGB color")),
+                };
+            }
+            "map_Kd" => {
+                // Options come before the file name
+                let file = tokens
+                    .last()
+                    .ok_or_else(|| line_error(line_number, "`map_Kd` without a file"))?;
+                material.diffuse_texture = Some(file.to_string());
+            }
+            // Lighting, transparency and other maps aren't supported by the built in shaders
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+/// Saves `mesh` as an OBJ file in the user data directory, see [`write`].
+/// With a `texture`, its material is saved next to it with the extension changed to `.mtl`.
+pub fn save(ctx: &Context, path: &str, mesh: &Mesh3d, texture: Option<&str>) -> GameResult {
+    let material_lib = texture.map(|texture| {
+        let stem = match path.rfind('.') {
+            Some(dot) if dot > path.rfind('/').unwrap_or(0) => &path[..dot],
+            _ => path,
+        };
+        (format!("{stem}.mtl"), texture)
+    });
+    let lib_name = material_lib
+        .as_ref()
+        .map(|(lib_path, _)| lib_path.rsplit('/').next().unwrap_or(lib_path));
+    write_bytes(ctx, path, write(mesh, lib_name)?.as_bytes())?;
+    if let Some((lib_path, texture)) = &material_lib {
+        write_bytes(ctx, lib_path, write_mtl(texture)?.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Serializes a triangle list `mesh` as OBJ source. With a `material_lib`, the faces use
+/// the material [`write_mtl`] writes to it. Vertex colors are written for vertices with
+/// any coverage, and are read back with full coverage. Normals are left out if any of them
+/// is zero.
+pub fn write(mesh: &Mesh3d, material_lib: Option<&str>) -> GameResult<String> {
+    if mesh.topology != wgpu::PrimitiveTopology::TriangleList {
+        return Err(GameError::CustomError(format!(
+            "OBJ can only hold triangle lists, not {:?}",
+            mesh.topology
+        )));
+    }
+    if let Some(lib) = material_lib.filter(|lib| lib.contains(char::is_whitespace)) {
+        return Err(GameError::CustomError(format!(
+            "OBJ material library names can't contain spaces, `{lib}` does"
+        )));
+    }
+    let mut source = String::new();
+    if let Some(lib) = material_lib {
+        source.push_str(&format!("mtllib {lib}\n"));
+    }
+    for vertex in mesh.vertices.iter() {
+        let [x, y, z] = vertex.pos;
+        match vertex.color {
+            [r, g, b, a] if a > 0.0 => source.push_str(&format!("v {x} {y} {z} {r} {g} {b}\n")),
+            _ => source.push_str(&format!("v {x} {y} {z}\n")),
+        }
+    }
+    for vertex in mesh.vertices.iter() {
+        let [u, v] = vertex.tex_coord;
+        source.push_str(&format!("vt {u} {}\n", 1.0 - v));
+    }
+    // A zero normal isn't a direction, so either every vertex gets one or none do
+    let with_normals = mesh.vertices.iter().all(|vertex| vertex.normal != [0.0; 3]);
+    if with_normals {
+        for vertex in mesh.vertices.iter() {
+            let [x, y, z] = vertex.normal;
+            source.push_str(&format!("vn {x} {y} {z}\n"));
+        }
+    }
+    if material_lib.is_some() {
+        source.push_str(&format!("usemtl {MATERIAL}\n"));
+    }
+    for triangle in mesh.indices.chunks_exact(3) {
+        source.push('f');
+        for &index in triangle {
+            let index = index as usize + 1;
+            if with_normals {
+                source.push_str(&format!(" {index}/{index}/{index}"));
+            } else {
+                source.push_str(&format!(" {index}/{index}"));
+            }
+        }
+        source.push('\n');
+    }
+    Ok(source)
+}
+
+/// MTL source with the one material [`write`] uses, textured with `texture`
+/// relative to the MTL file
+pub fn write_mtl(texture: &str) -> GameResult<String> {
+    // `map_Kd` takes the last token as the file
+    if texture.contains(char::is_whitespace) {
+        return Err(GameError::CustomError(format!(
+            "MTL texture paths can't contain spaces, `{texture}` does"
+        )));
+    }
+    Ok(format!("newmtl {MATERIAL}\nKd 1 1 1\nmap_Kd {texture}\n"))
+}
+
+fn group_index(
+    data: &mut ObjData,
+    lookup: &mut HashMap<Option<String>, usize>,
+    vertex_lookup: &mut Vec<HashMap<FaceVertex, u32>>,
+    material: Option<String>,
+) -> usize {
+    *lookup.entry(material.clone()).or_insert_with(|| {
+        data.groups.push(ObjGroup {
+            material,
+            ..Default::default()
+        });
+        vertex_lookup.push(HashMap::new());
+        data.groups.len() - 1
+    })
+}
+
+/// Parses a `v`, `v/vt`, `v//vn` or `v/vt/vn` face vertex into zero based indices
+fn face_vertex(
+    token: &str,
+    counts: (usize, usize, usize),
+    line_number: usize,
+) -> GameResult<FaceVertex> {
+    let mut parts = token.split('/');
+    let position = parts
+        .next()
+        .filter(|part| !part.is_empty())
+        .ok_or_else(|| {
+            line_error(
+                line_number,
+                format!("face vertex `{token}` has no position"),
+            )
+        })?;
+    let position = index(position, counts.0, line_number)?;
+    let tex_coord = match parts.next() {
+        Some(part) if !part.is_empty() => Some(index(part, counts.1, line_number)?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(part) if !part.is_empty() => Some(index(part, counts.2, line_number)?),
+        _ => None,
+    };
+    if parts.next().is_some() {
+        return Err(line_error(
+            line_number,
+            format!("face vertex `{token}` has too many parts"),
+        ));
+    }
+    Ok((position, tex_coord, normal))
+}
+
+/// Converts a one based index, or a negative one counting back from the end, to zero based
+fn index(token: &str, count: usize, line_number: usize) -> GameResult<usize> {
+    let value: i64 = token
+        .parse()
+        .map_err(|_| line_error(line_number, format!("`{token}` is not an index")))?;
+    let index = match value {
+        1.. => value - 1,
+        ..=-1 => count as i64 + value,
+        0 => -1,
+    };
+    if index < 0 || index >= count as i64 {
+        return Err(line_error(
+            line_number,
+            format!("index {value} is out of range, there are {count}"),
+        ));
+    }
+    Ok(index as usize)
+}
+
+fn floats(tokens: SplitWhitespace, line_number: usize) -> GameResult<Vec<f32>> {
+    tokens
+        .map(|token: &str| {
+            token
+                .parse()
+                .map_err(|_| line_error(line_number, format!("`{token}` is not a number")))
+        })
+        .collect()
+}
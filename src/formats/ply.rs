@@ -0,0 +1,426 @@
+//! PLY, ASCII and binary little endian.
+//!
+//! Positions, normals, texture coordinates and colors are read from the `vertex` element and
+//! polygons from the `face` element, triangulated as fans. Files without faces are point clouds
+//! and load with a point list topology. Other elements and properties are skipped, apart from
+//! lists on vertices, which are rejected.
+
+use ggez::graphics;
+use ggez::{Context, GameError, GameResult};
+
+use super::{in_file, line_error, read_bytes};
+use crate::mesh::{Mesh3d, Vertex};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// Integer colors are stored as a fraction of this, floats are already in 0 to 1
+    fn color_scale(self) -> f32 {
+        match self {
+            Scalar::I8 | Scalar::U8 => 255.0,
+            Scalar::I16 | Scalar::U16 => 65535.0,
+            Scalar::I32 | Scalar::U32 => u32::MAX as f32,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Property {
+    Scalar(String, Scalar),
+    /// Name, then the types of the length and of the items
+    List(String, Scalar, Scalar),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(name, _) | Property::List(name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// The data after the header, read one property value at a time
+enum Body<'a> {
+    /// Tokens with the line they are on
+    Ascii(std::vec::IntoIter<(usize, &'a str)>),
+    BinaryLittleEndian(&'a [u8]),
+}
+
+impl Body<'_> {
+    fn read(&mut self, scalar: Scalar) -> GameResult<f64> {
+        let end =
+            || GameError::ResourceLoadError(String::from("file ends before its last element"));
+        match self {
+            Body::Ascii(tokens) => {
+                let (line_number, token) = tokens.next().ok_or_else(end)?;
+                token
+                    .parse()
+                    .map_err(|_| line_error(line_number, format!("`{token}` is not a number")))
+            }
+            Body::BinaryLittleEndian(bytes) => {
+                if bytes.len() < scalar.size() {
+                    return Err(end());
+                }
+                let (value, rest) = bytes.split_at(scalar.size());
+                *bytes = rest;
+                Ok(match scalar {
+                    Scalar::I8 => value[0] as i8 as f64,
+                    Scalar::U8 => value[0] as f64,
+                    Scalar::I16 => i16::from_le_bytes([value[0], value[1]]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([value[0], value[1]]) as f64,
+                    Scalar::I32 => i32::from_le_bytes(value.try_into().unwrap()) as f64,
+                    Scalar::U32 => u32::from_le_bytes(value.try_into().unwrap()) as f64,
+                    Scalar::F32 => f32::from_le_bytes(value.try_into().unwrap()) as f64,
+                    Scalar::F64 => f64::from_le_bytes(value.try_into().unwrap()),
+                })
+            }
+        }
+    }
+
+    /// The most instances of `element` the rest of the body could hold, so a header claiming
+    /// more can't make us reserve more than the file's size or loop over rows that take up
+    /// no space. An element without properties can't hold any.
+    fn max_rows(&self, element: &Element) -> usize {
+        let (left, row) = match self {
+            Body::Ascii(tokens) => (tokens.len(), element.properties.len()),
+            Body::BinaryLittleEndian(bytes) => {
+                let row = element
+                    .properties
+                    .iter()
+                    .map(|property| match *property {
+                        Property::Scalar(_, scalar) => scalar.size(),
+                        Property::List(_, length, _) => length.size(),
+                    })
+                    .sum();
+                (bytes.len(), row)
+            }
+        };
+        left.checked_div(row).unwrap_or(0)
+    }
+
+    /// Reads one instance of an element, with each list's items appended after its length
+    fn read_row(&mut self, element: &Element, row: &mut Vec<f64>) -> GameResult {
+        row.clear();
+        for property in element.properties.iter() {
+            match *property {
+                Property::Scalar(_, scalar) => row.push(self.read(scalar)?),
+                Property::List(_, length, item) => {
+                    let length = self.read(length)?;
+                    row.push(length);
+                    for _ in 0..length as usize {
+                        row.push(self.read(item)?);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Loads the PLY file at `path`
+pub fn load(ctx: &mut Context, path: &str) -> GameResult<Mesh3d> {
+    parse(&read_bytes(ctx, path)?).map_err(in_file(path))
+}
+
+/// Parses an ASCII or binary little endian PLY file.
+/// Vertex colors replace the texture, and their alpha is ignored.
+pub fn parse(bytes: &[u8]) -> GameResult<Mesh3d> {
+    let header_end = bytes
+        .windows(b"end_header".len())
+        .position(|window| window == b"end_header")
+        .and_then(|start| {
+            let newline = bytes[start..].iter().position(|&byte| byte == b'\n')?;
+            Some(start + newline + 1)
+        })
+        .ok_or_else(|| GameError::ResourceLoadError(String::from("no `end_header` line")))?;
+    let header = std::str::from_utf8(&bytes[..header_end])
+        .map_err(|_| GameError::ResourceLoadError(String::from("header is not valid UTF-8")))?;
+    let (binary, elements) = parse_header(header)?;
+
+    let body = &bytes[header_end..];
+    let mut body = if binary {
+        Body::BinaryLittleEndian(body)
+    } else {
+        let body = std::str::from_utf8(body)
+            .map_err(|_| GameError::ResourceLoadError(String::from("not valid UTF-8")))?;
+        let header_lines = header.lines().count();
+        let tokens: Vec<_> = body
+            .lines()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                line.split_whitespace()
+                    .map(move |token| (header_lines + i + 1, token))
+            })
+            .collect();
+        Body::Ascii(tokens.into_iter())
+    };
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut has_faces = false;
+    let mut row = Vec::new();
+    for element in elements.iter() {
+        let max_rows = body.max_rows(element);
+        if element.count > max_rows {
+            return Err(GameError::ResourceLoadError(format!(
+                "the file only has room for {max_rows} of the {} {} elements it claims",
+                element.count, element.name
+            )));
+        }
+        match element.name.as_str() {
+            "vertex" => {
+                let layout = VertexLayout::new(element)?;
+                vertices.reserve(element.count);
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                    vertices.push(layout.vertex(&row));
+                }
+            }
+            "face" => {
+                has_faces |= element.count > 0;
+                let list = element
+                    .properties
+                    .iter()
+                    .position(|property| {
+                        matches!(property, Property::List(..))
+                            && matches!(property.name(), "vertex_indices" | "vertex_index")
+                    })
+                    .ok_or_else(|| {
+                        GameError::ResourceLoadError(String::from(
+                            "`face` has no `vertex_indices` list",
+                        ))
+                    })?;
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                    // Lists are inlined into the row, so earlier ones move the indices along
+                    let mut start = 0;
+                    for property in element.properties[..list].iter() {
+                        start += match property {
+                            Property::Scalar(..) => 1,
+                            Property::List(..) => 1 + row[start] as usize,
+                        };
+                    }
+                    let length = row[start] as usize;
+                    let face = &row[start + 1..start + 1 + length];
+                    if face.len() < 3 {
+                        return Err(GameError::ResourceLoadError(format!(
+                            "face has {} vertices",
+                            face.len()
+                        )));
+                    }
+                    if let Some(index) = face.iter().find(|&&index| index < 0.0) {
+                        return Err(GameError::ResourceLoadError(format!(
+                            "face has the negative index {index}"
+                        )));
+                    }
+                    for i in 1..face.len() - 1 {
+                        indices.extend([face[0], face[i], face[i + 1]].map(|index| index as u32));
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    body.read_row(element, &mut row)?;
+                }
+            }
+        }
+    }
+
+    if let Some(&index) = indices
+        .iter()
+        .find(|&&index| index as usize >= vertices.len())
+    {
+        return Err(GameError::ResourceLoadError(format!(
+            "index {index} is out of range, there are {} vertices",
+            vertices.len()
+        )));
+    }
+    let topology = if has_faces {
+        wgpu::PrimitiveTopology::TriangleList
+    } else {
+        indices = (0..vertices.len() as u32).collect();
+        wgpu::PrimitiveTopology::PointList
+    };
+    Ok(Mesh3d {
+        vertices,
+        indices,
+        topology,
+        ..Default::default()
+    })
+}
+
+/// Returns whether the body is binary, and the elements it holds
+fn parse_header(header: &str) -> GameResult<(bool, Vec<Element>)> {
+    let mut lines = header.lines().enumerate();
+    if lines.next().map(|(_, line)| line.trim()) != Some("ply") {
+        return Err(GameError::ResourceLoadError(String::from(
+            "doesn't start with `ply`",
+        )));
+    }
+    let mut binary = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for (i, line) in lines {
+        let line_number = i + 1;
+        let tokens: Vec<_> = line.split_whitespace().collect();
+        match tokens[..] {
+            ["format", "ascii", _] => binary = Some(false),
+            ["format", "binary_little_endian", _] => binary = Some(true),
+            ["format", format, _] => {
+                return Err(line_error(
+                    line_number,
+                    format!("unsupported format `{format}`"),
+                ))
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| {
+                    line_error(line_number, format!("`{count}` is not an element count"))
+                })?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", length, item, name] => {
+                let property = Property::List(
+                    name.to_string(),
+                    scalar(length, line_number)?,
+                    scalar(item, line_number)?,
+                );
+                element_mut(&mut elements, line_number)?
+                    .properties
+                    .push(property);
+            }
+            ["property", scalar_type, name] => {
+                let property =
+                    Property::Scalar(name.to_string(), scalar(scalar_type, line_number)?);
+                element_mut(&mut elements, line_number)?
+                    .properties
+                    .push(property);
+            }
+            ["end_header"] => break,
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            _ => {
+                return Err(line_error(
+                    line_number,
+                    format!("unexpected `{}`", line.trim()),
+                ))
+            }
+        }
+    }
+    let binary =
+        binary.ok_or_else(|| GameError::ResourceLoadError(String::from("no `format` line")))?;
+    Ok((binary, elements))
+}
+
+fn element_mut(elements: &mut [Element], line_number: usize) -> GameResult<&mut Element> {
+    elements
+        .last_mut()
+        .ok_or_else(|| line_error(line_number, "`property` before any `element`"))
+}
+
+fn scalar(name: &str, line_number: usize) -> GameResult<Scalar> {
+    Scalar::parse(name).ok_or_else(|| line_error(line_number, format!("unknown type `{name}`")))
+}
+
+/// Where the attributes a [`Vertex`] needs are in a row of the `vertex` element
+struct VertexLayout {
+    position: [usize; 3],
+    normal: Option<[usize; 3]>,
+    tex_coord: Option<[usize; 2]>,
+    color: Option<([usize; 3], f32)>,
+}
+
+impl VertexLayout {
+    fn new(element: &Element) -> GameResult<Self> {
+        let mut columns = Vec::new();
+        for property in element.properties.iter() {
+            match property {
+                Property::Scalar(name, scalar) => columns.push((name.as_str(), *scalar)),
+                Property::List(name, ..) => {
+                    return Err(GameError::ResourceLoadError(format!(
+                        "`vertex` has the list property `{name}`"
+                    )))
+                }
+            }
+        }
+        let find = |names: &[&str]| columns.iter().position(|(name, _)| names.contains(name));
+        let find_all =
+            |names: [&[&str]; 3]| Some([find(names[0])?, find(names[1])?, find(names[2])?]);
+
+        let position = find_all([&["x"], &["y"], &["z"]]).ok_or_else(|| {
+            GameError::ResourceLoadError(String::from("`vertex` has no x, y and z properties"))
+        })?;
+        let normal = find_all([&["nx"], &["ny"], &["nz"]]);
+        let tex_coord = find(&["s", "u", "texture_u"])
+            .zip(find(&["t", "v", "texture_v"]))
+            .map(|(u, v)| [u, v]);
+        let color = find_all([
+            &["red", "diffuse_red"],
+            &["green", "diffuse_green"],
+            &["blue", "diffuse_blue"],
+        ])
+        .map(|color| (color, columns[color[0]].1.color_scale()));
+        Ok(Self {
+            position,
+            normal,
+            tex_coord,
+            color,
+        })
+    }
+
+    fn vertex(&self, row: &[f64]) -> Vertex {
+        let position = self.position.map(|i| row[i] as f32);
+        // Like OBJ, v = 0 is the bottom of the image
+        let tex_coord = self
+            .tex_coord
+            .map_or([0.0; 2], |[u, v]| [row[u] as f32, 1.0 - row[v] as f32]);
+        let color = self.color.map(|(color, scale)| {
+            let [r, g, b] = color.map(|i| row[i] as f32 / scale);
+            graphics::Color::new(r, g, b, 1.0)
+        });
+        let vertex = Vertex::new(position, tex_coord, color);
+        match self.normal {
+            Some(normal) => vertex.normal(normal.map(|i| row[i] as f32)),
+            None => vertex,
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! STL, both ASCII and binary.
+//!
+//! Every triangle gets its own three vertices with a normal computed from its winding,
+//! the normals stored in the file are often zero or wrong. Binary color extensions are skipped.
+
+use ggez::{Context, GameError, GameResult};
+use glam::Vec3;
+
+use super::{in_file, line_error, read_bytes};
+use crate::mesh::{Mesh3d, Vertex};
+
+/// Size of the binary header, then the triangle count
+const HEADER_LEN: usize = 84;
+/// Normal, three corners and the attribute byte count
+const TRIANGLE_LEN: usize = 50;
+
+/// Loads the STL file at `path`
+pub fn load(ctx: &mut Context, path: &str) -> GameResult<Mesh3d> {
+    parse(&read_bytes(ctx, path)?).map_err(in_file(path))
+}
+
+/// Parses ASCII or binary STL, telling them apart by size as binary headers may start with `solid`
+pub fn parse(bytes: &[u8]) -> GameResult<Mesh3d> {
+    if bytes.len() >= HEADER_LEN {
+        let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+        if bytes.len() == HEADER_LEN + count * TRIANGLE_LEN {
+            return Ok(parse_binary(&bytes[HEADER_LEN..]));
+        }
+    }
+    if bytes.trim_ascii_start().starts_with(b"solid") {
+        let source = std::str::from_utf8(bytes)
+            .map_err(|_| GameError::ResourceLoadError(String::from("not valid UTF-8")))?;
+        return parse_ascii(source);
+    }
+    Err(GameError::ResourceLoadError(String::from(
+        "neither ASCII STL nor binary STL of the size its header gives",
+    )))
+}
+
+fn parse_binary(triangles: &[u8]) -> Mesh3d {
+    let corners = triangles.chunks_exact(TRIANGLE_LEN).map(|triangle| {
+        // Skips the stored normal
+        let corner = |i: usize| {
+            let start = 12 + i * 12;
+            let value = |offset: usize| {
+                f32::from_le_bytes(triangle[offset..offset + 4].try_into().unwrap())
+            };
+            Vec3::new(value(start), value(start + 4), value(start + 8))
+        };
+        [corner(0), corner(1), corner(2)]
+    });
+    mesh(corners)
+}
+
+fn parse_ascii(source: &str) -> GameResult<Mesh3d> {
+    let mut triangles = Vec::new();
+    let mut corners = Vec::with_capacity(3);
+    let mut in_loop = false;
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        match keyword {
+            "vertex" if in_loop => {
+                let mut value = || -> GameResult<f32> {
+                    let token = tokens
+                        .next()
+                        .ok_or_else(|| line_error(line_number, "vertex has too few values"))?;
+                    token
+                        .parse()
+                        .map_err(|_| line_error(line_number, format!("`{token}` is not a number")))
+                };
+                corners.push(Vec3::new(value()?, value()?, value()?));
+            }
+            "outer" if !in_loop => {
+                in_loop = true;
+                corners.clear();
+            }
+            "endloop" if in_loop => {
+                in_loop = false;
+                let [a, b, c] = corners[..] else {
+                    return Err(line_error(
+                        line_number,
+                        format!("facet has {} vertices", corners.len()),
+                    ));
+                };
+                triangles.push([a, b, c]);
+            }
+            "solid" | "endsolid" | "facet" | "endfacet" if !in_loop => {}
+            _ => return Err(line_error(line_number, format!("unexpected `{keyword}`"))),
+        }
+    }
+    if in_loop {
+        return Err(GameError::ResourceLoadError(String::from(
+            "file ends inside a facet",
+        )));
+    }
+    Ok(mesh(triangles))
+}
+
+fn mesh(triangles: impl IntoIterator<Item = [Vec3; 3]>) -> Mesh3d {
+    let mut vertices = Vec::new();
+    for [a, b, c] in triangles {
+        let normal = (b - a).cross(c - a).normalize_or_zero();
+        vertices
+            .extend([a, b, c].map(|corner| Vertex::new(corner, [0.0, 0.0], None).normal(normal)));
+    }
+    Mesh3d {
+        indices: (0..vertices.len() as u32).collect(),
+        vertices,
+        ..Default::default()
+    }
+}
@@ -1,10 +1,13 @@
 pub mod camera;
 pub mod canvas;
+pub mod formats;
 pub mod mesh;
 pub mod render;
+mod shapes;
 
 pub mod prelude {
     pub use crate::camera::{Camera, CameraBundle, Projection};
-    pub use crate::canvas::{Canvas3d, DrawState3d};
-    pub use crate::mesh::{Mesh3d, Vertex};
+    pub use crate::canvas::{Canvas3d, DepthState3d, DrawParam3d, DrawState3d};
+    pub use crate::mesh::{Instance3d, InstanceBatch, Mesh3d, MeshHandle, Transform3d, Vertex};
+    pub use crate::render::Renderer3d;
 }
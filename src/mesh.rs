@@ -1,10 +1,10 @@
 use ggez::graphics::Image;
-use ggez::{graphics, Context};
+use ggez::{graphics, Context, GameError, GameResult};
 use glam::{Mat4, Vec3};
 use mint::{Vector2, Vector3};
+use std::ops::Deref;
 use std::sync::Arc;
 use wgpu::util::DeviceExt;
-use wgpu::RenderPipeline;
 
 use crate::canvas::DrawParam3d;
 
@@ -26,8 +26,6 @@ impl Default for Aabb {
 impl Aabb {
     #[inline]
     pub fn from_min_max(minimum: Vec3, maximum: Vec3) -> Self {
-        let minimum = minimum;
-        let maximum = maximum;
         let center = 0.5 * (maximum + minimum);
         let half_extents = 0.5 * (maximum - minimum);
         Self {
@@ -57,8 +55,8 @@ impl Default for Transform3d {
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Instance3d {
-    transform: [[f32; 4]; 4],
-    color: [f32; 4],
+    pub transform: [[f32; 4]; 4],
+    pub color: [f32; 4],
 }
 
 impl Default for Instance3d {
@@ -68,6 +66,22 @@ impl Default for Instance3d {
 }
 
 impl Instance3d {
+    pub fn new<M>(transform: M, color: graphics::Color) -> Self
+    where
+        M: Into<mint::ColumnMatrix4<f32>>,
+    {
+        let transform = Mat4::from(transform.into());
+        Self {
+            transform: [
+                transform.x_axis.into(),
+                transform.y_axis.into(),
+                transform.z_axis.into(),
+                transform.w_axis.into(),
+            ],
+            color: color.into(),
+        }
+    }
+
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
@@ -108,14 +122,12 @@ impl Instance3d {
     where
         V: Into<mint::Vector3<f32>>,
     {
-        let pivot: mint::Vector3<f32> = center.into();
-        let transform =
-            Mat4::from_translation(Vec3::from(param.transform.position) + Vec3::from(pivot))
-                * Mat4::from_scale(param.transform.scale.into())
-                * Mat4::from_quat(param.transform.rotation.into())
-                * Mat4::from_translation(
-                    (Vec3::from(param.transform.position) + Vec3::from(pivot)) * -1.0,
-                );
+        // Scales and rotates around the pivot, then moves by the position
+        let pivot = Vec3::from(center.into());
+        let transform = Mat4::from_translation(Vec3::from(param.transform.position) + pivot)
+            * Mat4::from_scale(param.transform.scale.into())
+            * Mat4::from_quat(param.transform.rotation.into())
+            * Mat4::from_translation(-pivot);
 
         Self {
             transform: [
@@ -129,6 +141,112 @@ impl Instance3d {
     }
 }
 
+/// Instances kept in their own GPU buffer so static geometry doesn't have to be
+/// re-uploaded every frame, draw it with [`Canvas3d::draw_batch`](crate::canvas::Canvas3d::draw_batch)
+pub struct InstanceBatch {
+    pub buffer: Arc<wgpu::Buffer>,
+    len: u32,
+    capacity: u32,
+    /// Copies of the instances' transforms, `update` can change any of them
+    transforms: Vec<Mat4>,
+    /// Mean of `transforms`, which moves a point to the mean of where each instance puts it
+    mean_transform: Mat4,
+}
+
+impl InstanceBatch {
+    pub fn new(ctx: &mut Context, instances: &[Instance3d]) -> Self {
+        let mut batch = Self {
+            buffer: Arc::new(Self::create_buffer(ctx, instances.len() as u32)),
+            len: 0,
+            capacity: instances.len() as u32,
+            transforms: Vec::new(),
+            mean_transform: Mat4::IDENTITY,
+        };
+        batch.set(ctx, instances);
+        batch
+    }
+
+    fn create_buffer(ctx: &mut Context, capacity: u32) -> wgpu::Buffer {
+        ctx.gfx
+            .wgpu()
+            .device
+            .create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Instance Batch Buffer"),
+                // wgpu doesn't allow binding empty buffers
+                size: (capacity.max(1) as usize * std::mem::size_of::<Instance3d>())
+                    as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Mean of the instances' transforms, transparent batches are sorted by where it
+    /// moves the center of the mesh's bounds
+    pub(crate) fn mean_transform(&self) -> Mat4 {
+        self.mean_transform
+    }
+
+    fn update_transforms(&mut self, offset: usize, instances: &[Instance3d]) {
+        self.transforms.truncate(self.len as usize);
+        self.transforms.resize(self.len as usize, Mat4::IDENTITY);
+        for (transform, instance) in self.transforms[offset..].iter_mut().zip(instances) {
+            *transform = Mat4::from_cols_array_2d(&instance.transform);
+        }
+        self.mean_transform = if self.transforms.is_empty() {
+            Mat4::IDENTITY
+        } else {
+            self.transforms.iter().sum::<Mat4>() * (1.0 / self.transforms.len() as f32)
+        };
+    }
+
+    /// Replaces every instance, only reallocating the buffer if it has to grow
+    pub fn set(&mut self, ctx: &mut Context, instances: &[Instance3d]) {
+        let len = instances.len() as u32;
+        if len > self.capacity {
+            self.capacity = len.next_power_of_two();
+            self.buffer = Arc::new(Self::create_buffer(ctx, self.capacity));
+        }
+        ctx.gfx
+            .wgpu()
+            .queue
+            .write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+        self.len = len;
+        self.update_transforms(0, instances);
+    }
+
+    /// Overwrites the instances starting at `offset`, leaving the rest of the batch untouched
+    pub fn update(
+        &mut self,
+        ctx: &mut Context,
+        offset: u32,
+        instances: &[Instance3d],
+    ) -> GameResult {
+        if offset as usize + instances.len() > self.len as usize {
+            return Err(GameError::RenderError(format!(
+                "cannot update instances {}..{} of a batch with {} instances",
+                offset,
+                offset as usize + instances.len(),
+                self.len
+            )));
+        }
+        ctx.gfx.wgpu().queue.write_buffer(
+            &self.buffer,
+            (offset as usize * std::mem::size_of::<Instance3d>()) as wgpu::BufferAddress,
+            bytemuck::cast_slice(instances),
+        );
+        self.update_transforms(offset as usize, instances);
+        Ok(())
+    }
+}
+
 // TODO: Allow custom vertex formats
 #[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
 #[repr(C)]
@@ -136,6 +254,8 @@ pub struct Vertex {
     pub pos: [f32; 3],
     pub tex_coord: [f32; 2],
     pub color: [f32; 4],
+    /// Zero unless set, the built in shaders don't light anything
+    pub normal: [f32; 3],
 }
 
 impl Vertex {
@@ -155,9 +275,19 @@ impl Vertex {
             pos: position.into(),
             tex_coord: uv.into(),
             color,
+            normal: [0.0; 3],
         }
     }
 
+    pub fn normal<N>(mut self, normal: N) -> Self
+    where
+        N: Into<Vector3<f32>>,
+    {
+        let normal: Vector3<f32> = normal.into();
+        self.normal = normal.into();
+        self
+    }
+
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as _,
@@ -181,23 +311,46 @@ impl Vertex {
                     offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
                     shader_location: 2,
                 },
+                // normal
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x3,
+                    offset: std::mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                },
             ],
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Mesh3d {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
     pub vert_buffer: Option<Arc<wgpu::Buffer>>,
     pub ind_buffer: Option<Arc<wgpu::Buffer>>,
-    pub bind_group: Option<Arc<wgpu::BindGroup>>,
     pub texture: Option<Image>,
+    pub sampler: graphics::Sampler,
+    /// How `indices` are assembled into primitives.
+    /// Strips can be restarted with an index of `u32::MAX`.
+    pub topology: wgpu::PrimitiveTopology,
 }
 
 impl Mesh3d {
     pub fn gen_wgpu_buffer(&mut self, ctx: &mut Context) {
+        let (verts, inds) = self.create_wgpu_buffers(ctx);
+        self.vert_buffer = Some(verts);
+        self.ind_buffer = Some(inds);
+    }
+
+    /// Returns the vertex and index buffers, reusing the ones from [`Mesh3d::gen_wgpu_buffer`] if present
+    pub fn wgpu_buffers(&self, ctx: &mut Context) -> (Arc<wgpu::Buffer>, Arc<wgpu::Buffer>) {
+        match (&self.vert_buffer, &self.ind_buffer) {
+            (Some(verts), Some(inds)) => (verts.clone(), inds.clone()),
+            _ => self.create_wgpu_buffers(ctx),
+        }
+    }
+
+    fn create_wgpu_buffers(&self, ctx: &mut Context) -> (Arc<wgpu::Buffer>, Arc<wgpu::Buffer>) {
         let verts = ctx
             .gfx
             .wgpu()
@@ -217,49 +370,7 @@ impl Mesh3d {
                 usage: wgpu::BufferUsages::INDEX,
             });
 
-        self.vert_buffer = Some(Arc::new(verts));
-        self.ind_buffer = Some(Arc::new(inds));
-    }
-
-    pub fn gen_bind_group(&mut self, pipeline: &RenderPipeline, ctx: &mut Context) {
-        // Allow custom one set through mesh
-        let sampler = ctx
-            .gfx
-            .wgpu()
-            .device
-            .create_sampler(&graphics::Sampler::default().into());
-
-        let bind_group = ctx
-            .gfx
-            .wgpu()
-            .device
-            .create_bind_group(&wgpu::BindGroupDescriptor {
-                label: None,
-                layout: &pipeline.get_bind_group_layout(0),
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(
-                            self.texture
-                                .as_ref()
-                                .unwrap_or(&Image::from_color(
-                                    ctx,
-                                    1,
-                                    1,
-                                    Some(graphics::Color::WHITE),
-                                ))
-                                .wgpu()
-                                .1,
-                        ),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&sampler),
-                    },
-                ],
-            });
-
-        self.bind_group = Some(Arc::new(bind_group));
+        (Arc::new(verts), Arc::new(inds))
     }
 
     pub fn to_aabb(&self) -> Option<Aabb> {
@@ -269,12 +380,12 @@ impl Mesh3d {
             minimum = minimum.min(Vec3::from_array(p.pos));
             maximum = maximum.max(Vec3::from_array(p.pos));
         }
-        if minimum.x != std::f32::MAX
-            && minimum.y != std::f32::MAX
-            && minimum.z != std::f32::MAX
-            && maximum.x != std::f32::MIN
-            && maximum.y != std::f32::MIN
-            && maximum.z != std::f32::MIN
+        if minimum.x != f32::MAX
+            && minimum.y != f32::MAX
+            && minimum.z != f32::MAX
+            && maximum.x != f32::MIN
+            && maximum.y != f32::MIN
+            && maximum.z != f32::MIN
         {
             Some(Aabb::from_min_max(minimum, maximum))
         } else {
@@ -282,3 +393,33 @@ impl Mesh3d {
         }
     }
 }
+
+/// GPU side of a [`Mesh3d`], see [`Renderer3d::upload_mesh`](crate::render::Renderer3d::upload_mesh)
+pub struct GpuMesh {
+    pub vert_buffer: Arc<wgpu::Buffer>,
+    pub ind_buffer: Arc<wgpu::Buffer>,
+    pub index_count: u32,
+    pub topology: wgpu::PrimitiveTopology,
+    pub bind_group: Arc<wgpu::BindGroup>,
+    /// Bounds of the vertices, computed once at upload
+    pub aabb: Aabb,
+}
+
+/// Cheap to clone reference to a mesh that already lives on the GPU
+#[derive(Clone)]
+pub struct MeshHandle(pub Arc<GpuMesh>);
+
+impl MeshHandle {
+    /// Identifies the uploaded mesh, clones of a handle share the same id
+    pub fn id(&self) -> usize {
+        Arc::as_ptr(&self.0) as usize
+    }
+}
+
+impl Deref for MeshHandle {
+    type Target = GpuMesh;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
@@ -0,0 +1,822 @@
+use ggez::graphics::Shader;
+use ggez::{graphics, Context, GameError, GameResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::camera::{CameraBundle, CameraUniform};
+use crate::canvas::{DepthState3d, DrawState3d};
+use crate::mesh::{GpuMesh, Instance3d, Mesh3d, MeshHandle, Vertex};
+
+/// Number of instances the instance buffer can hold before it has to grow
+const INITIAL_INSTANCE_CAPACITY: usize = 64;
+
+/// Number of cameras the camera buffer can hold before it has to grow
+const INITIAL_CAMERA_CAPACITY: usize = 4;
+
+/// Frames a shared attachment image or cached bind group may go unused before it is dropped
+const ATTACHMENT_LIFETIME: usize = 2;
+
+/// Sum of weighted premultiplied colors, and the sum of weights in alpha
+pub const OIT_ACCUM_FORMAT: graphics::ImageFormat = graphics::ImageFormat::Rgba16Float;
+
+/// Product of one minus each fragment's alpha, how much of the background still shows
+pub const OIT_REVEAL_FORMAT: graphics::ImageFormat = graphics::ImageFormat::R8Unorm;
+
+/// Everything a cached pipeline depends on
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PipelineKey3d {
+    pub state: DrawState3d,
+    /// Format of the image being drawn to
+    pub format: graphics::ImageFormat,
+    /// Topology of the mesh being drawn
+    pub topology: wgpu::PrimitiveTopology,
+    /// Samples per pixel of the attachments
+    pub sample_count: u32,
+}
+
+/// Accumulation and revealage images for order independent transparency,
+/// with the bind group the resolve pass reads them through
+#[derive(Clone)]
+pub struct OitTargets {
+    pub accum: graphics::Image,
+    pub reveal: graphics::Image,
+    pub bind_group: Arc<wgpu::BindGroup>,
+}
+
+/// Long lived GPU state shared by every [`Canvas3d`](crate::canvas::Canvas3d).
+///
+/// Create this once and keep it around, a canvas only borrows it for a single frame.
+pub struct Renderer3d {
+    pub default_shader: Shader,
+    /// One pipeline per [`PipelineKey3d`] used so far, built on first use
+    pub pipelines: HashMap<PipelineKey3d, wgpu::RenderPipeline>,
+    pub pipeline_layout: wgpu::PipelineLayout,
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    /// Bound in place of a texture for meshes that don't have one
+    pub white: graphics::Image,
+    samplers: HashMap<graphics::Sampler, wgpu::Sampler>,
+    /// Keyed by the address of the texture view, along with the tick they were last used.
+    /// The cached [`graphics::Image`] keeps that address from being reused.
+    bind_groups:
+        HashMap<(usize, graphics::Sampler), (graphics::Image, Arc<wgpu::BindGroup>, usize)>,
+    /// Depth and multisampled images shared by every target of the same size,
+    /// keyed by format, width, height and sample count, along with the tick they were last used
+    attachments: HashMap<(graphics::ImageFormat, u32, u32, u32), (graphics::Image, usize)>,
+    /// Order independent transparency targets by size, along with the tick they were last used
+    oit_targets: HashMap<(u32, u32), (OitTargets, usize)>,
+    oit_bind_group_layout: wgpu::BindGroupLayout,
+    oit_resolve_layout: wgpu::PipelineLayout,
+    oit_resolve_shader: wgpu::ShaderModule,
+    /// One resolve pipeline per target format
+    oit_resolve_pipelines: HashMap<graphics::ImageFormat, wgpu::RenderPipeline>,
+    pub camera_uniform: CameraUniform,
+    /// One camera per canvas of the current frame, `camera_stride` bytes apart
+    pub camera_buffer: wgpu::Buffer,
+    /// Binds one camera of `camera_buffer` at a dynamic offset
+    pub camera_bind_group: wgpu::BindGroup,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    /// Size of a camera rounded up to the device's uniform offset alignment
+    camera_stride: u64,
+    /// Number of cameras that fit in `camera_buffer`
+    pub camera_capacity: usize,
+    /// Instances of every canvas of the current frame, one after the other
+    pub instance_buffer: wgpu::Buffer,
+    /// Number of [`Instance3d`]s that fit in `instance_buffer`
+    pub instance_capacity: usize,
+    /// Cameras and instances handed out to canvases since `frame_tick`
+    camera_count: usize,
+    instance_count: usize,
+    /// Tick of the frame the buffers are being filled for
+    frame_tick: usize,
+}
+
+impl Renderer3d {
+    pub fn new(ctx: &mut Context) -> Self {
+        let cube_code = include_str!("../resources/cube.wgsl");
+        let shader = graphics::ShaderBuilder::from_code(cube_code)
+            .build(&ctx.gfx)
+            .unwrap(); // Should never fail since cube.wgsl is unchanging
+
+        let camera_uniform = CameraUniform::new();
+        let camera_stride = wgpu::util::align_to(
+            std::mem::size_of::<CameraUniform>() as u64,
+            u64::from(
+                ctx.gfx
+                    .wgpu()
+                    .device
+                    .limits()
+                    .min_uniform_buffer_offset_alignment,
+            ),
+        );
+        let camera_buffer = Self::create_camera_buffer(ctx, camera_stride, INITIAL_CAMERA_CAPACITY);
+
+        let instance_buffer = Self::create_instance_buffer(ctx, INITIAL_INSTANCE_CAPACITY);
+
+        let camera_bind_group_layout =
+            ctx.gfx
+                .wgpu()
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(
+                                std::mem::size_of::<CameraUniform>() as u64,
+                            ),
+                        },
+                        count: None,
+                    }],
+                    label: Some("camera_bind_group_layout"),
+                });
+        let texture_bind_group_layout =
+            ctx.gfx
+                .wgpu()
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                    label: Some("texture_bind_group_layout"),
+                });
+
+        let camera_bind_group =
+            Self::create_camera_bind_group(ctx, &camera_bind_group_layout, &camera_buffer);
+
+        let pipeline_layout =
+            ctx.gfx
+                .wgpu()
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Render Pipeline Layout"),
+                    bind_group_layouts: &[&texture_bind_group_layout, &camera_bind_group_layout],
+                    push_constant_ranges: &[],
+                });
+
+        let oit_texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let oit_bind_group_layout =
+            ctx.gfx
+                .wgpu()
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    entries: &[oit_texture_entry(0), oit_texture_entry(1)],
+                    label: Some("oit_bind_group_layout"),
+                });
+        let oit_resolve_layout =
+            ctx.gfx
+                .wgpu()
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("OIT Resolve Pipeline Layout"),
+                    bind_group_layouts: &[&oit_bind_group_layout],
+                    push_constant_ranges: &[],
+                });
+        let oit_resolve_shader =
+            ctx.gfx
+                .wgpu()
+                .device
+                .create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("OIT Resolve Shader"),
+                    source: wgpu::ShaderSource::Wgsl(
+                        include_str!("../resources/oit_resolve.wgsl").into(),
+                    ),
+                });
+
+        Renderer3d {
+            default_shader: shader,
+            pipelines: HashMap::default(),
+            pipeline_layout,
+            texture_bind_group_layout,
+            white: graphics::Image::from_color(ctx, 1, 1, Some(graphics::Color::WHITE)),
+            samplers: HashMap::default(),
+            bind_groups: HashMap::default(),
+            attachments: HashMap::default(),
+            oit_targets: HashMap::default(),
+            oit_bind_group_layout,
+            oit_resolve_layout,
+            oit_resolve_shader,
+            oit_resolve_pipelines: HashMap::default(),
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group,
+            camera_bind_group_layout,
+            camera_stride,
+            camera_capacity: INITIAL_CAMERA_CAPACITY,
+            instance_buffer,
+            instance_capacity: INITIAL_INSTANCE_CAPACITY,
+            camera_count: 0,
+            instance_count: 0,
+            frame_tick: 0,
+        }
+    }
+
+    /// The state a fresh canvas starts out with
+    pub fn default_state(&self) -> DrawState3d {
+        DrawState3d {
+            shader: self.default_shader.clone(),
+            depth: DepthState3d::default(),
+            blend_mode: graphics::BlendMode::REPLACE,
+            oit: false,
+            cull_mode: Some(wgpu::Face::Back),
+            front_face: wgpu::FrontFace::Ccw,
+            polygon_mode: wgpu::PolygonMode::Fill,
+        }
+    }
+
+    /// Whether the device can draw with `polygon_mode`, `Fill` always works
+    pub fn polygon_mode_supported(ctx: &Context, polygon_mode: wgpu::PolygonMode) -> bool {
+        let features = ctx.gfx.wgpu().device.features();
+        match polygon_mode {
+            wgpu::PolygonMode::Fill => true,
+            wgpu::PolygonMode::Line => features.contains(wgpu::Features::POLYGON_MODE_LINE),
+            wgpu::PolygonMode::Point => features.contains(wgpu::Features::POLYGON_MODE_POINT),
+        }
+    }
+
+    /// Builds the pipeline for `key` unless one is already cached.
+    /// Fails if the shader doesn't fit, such as when `fs_oit` is missing for
+    /// order independent transparency.
+    pub fn update_pipeline(&mut self, ctx: &mut Context, key: &PipelineKey3d) -> GameResult {
+        if self.pipelines.contains_key(key) {
+            return Ok(());
+        }
+        let state = &key.state;
+        let polygon_mode = if Self::polygon_mode_supported(ctx, state.polygon_mode) {
+            state.polygon_mode
+        } else {
+            wgpu::PolygonMode::Fill
+        };
+        let targets = if state.oit {
+            // Accumulation adds up, revealage multiplies by one minus each alpha
+            vec![
+                Some(wgpu::ColorTargetState {
+                    format: OIT_ACCUM_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                Some(wgpu::ColorTargetState {
+                    format: OIT_REVEAL_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+            ]
+        } else {
+            vec![Some(wgpu::ColorTargetState {
+                format: key.format,
+                blend: Some(wgpu::BlendState {
+                    color: state.blend_mode.color,
+                    alpha: state.blend_mode.alpha,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })]
+        };
+
+        let device = &ctx.gfx.wgpu().device;
+        // Caught here rather than by wgpu's handler, which panics
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline"),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: state.shader.vs_module().as_ref().unwrap_or(
+                    self.default_shader.vs_module().as_ref().unwrap(), // Should always exist
+                ),
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc(), Instance3d::desc()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: key.topology,
+                strip_index_format: if key.topology.is_strip() {
+                    Some(wgpu::IndexFormat::Uint32)
+                } else {
+                    None
+                },
+                front_face: state.front_face,
+                cull_mode: state.cull_mode,
+                unclipped_depth: false,
+                polygon_mode,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                // Transparent fragments must not hide each other
+                depth_write_enabled: state.depth.write && !state.oit,
+                depth_compare: if state.depth.test {
+                    state.depth.compare
+                } else {
+                    wgpu::CompareFunction::Always
+                },
+                stencil: wgpu::StencilState::default(),
+                bias: state.depth.bias,
+            }),
+            multisample: wgpu::MultisampleState {
+                count: key.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: state
+                    .shader
+                    .fs_module()
+                    .as_ref()
+                    .unwrap_or(self.default_shader.fs_module().as_ref().unwrap()), // Should always exist since we use the default
+                entry_point: if state.oit { "fs_oit" } else { "fs_main" },
+                targets: &targets,
+            }),
+            multiview: None,
+        });
+        if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+            let entry_point = if state.oit { "fs_oit" } else { "fs_main" };
+            return Err(GameError::RenderError(format!(
+                "can't build a pipeline from the shader's `vs_main` and `{entry_point}`: {error}"
+            )));
+        }
+
+        self.pipelines.insert(key.clone(), pipeline);
+        Ok(())
+    }
+
+    /// Returns the bind group for `texture` and `sampler`, creating it only the first time they are used together
+    pub fn bind_group(
+        &mut self,
+        ctx: &mut Context,
+        texture: Option<&graphics::Image>,
+        sampler: graphics::Sampler,
+    ) -> Arc<wgpu::BindGroup> {
+        let tick = ctx.time.ticks();
+        // Bind groups no mesh holds on to anymore only keep their textures alive
+        self.bind_groups.retain(|_, (_, bind_group, last_used)| {
+            Arc::strong_count(bind_group) > 1 || tick - *last_used <= ATTACHMENT_LIFETIME
+        });
+        let texture = texture.unwrap_or(&self.white);
+        let key = (
+            texture.wgpu().1 as *const wgpu::TextureView as usize,
+            sampler,
+        );
+        if let Some((_, bind_group, last_used)) = self.bind_groups.get_mut(&key) {
+            *last_used = tick;
+            return bind_group.clone();
+        }
+
+        let device = &ctx.gfx.wgpu().device;
+        let sampler = self
+            .samplers
+            .entry(sampler)
+            .or_insert_with(|| device.create_sampler(&sampler.into()));
+        let bind_group = Arc::new(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(texture.wgpu().1),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        }));
+
+        self.bind_groups
+            .insert(key, (texture.clone(), bind_group.clone(), tick));
+        bind_group
+    }
+
+    /// Uploads `mesh` once so it can be drawn every frame without cloning its vertices
+    pub fn upload_mesh(&mut self, ctx: &mut Context, mesh: &Mesh3d) -> MeshHandle {
+        let (vert_buffer, ind_buffer) = mesh.wgpu_buffers(ctx);
+        let bind_group = self.bind_group(ctx, mesh.texture.as_ref(), mesh.sampler);
+        MeshHandle(Arc::new(GpuMesh {
+            vert_buffer,
+            ind_buffer,
+            index_count: mesh.indices.len() as u32,
+            topology: mesh.topology,
+            bind_group,
+            aabb: mesh.to_aabb().unwrap_or_default(),
+        }))
+    }
+
+    /// Drops every cached bind group along with the textures they keep alive
+    pub fn clear_bind_groups(&mut self) {
+        self.bind_groups.clear();
+    }
+
+    /// Returns a depth image of the given size, shared with other canvases of that size
+    pub fn depth_image(
+        &mut self,
+        ctx: &mut Context,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> graphics::Image {
+        self.attachment(
+            ctx,
+            graphics::ImageFormat::Depth32Float,
+            width,
+            height,
+            sample_count,
+        )
+    }
+
+    /// Returns a multisampled image to draw to before resolving into a target of `format`
+    pub fn msaa_image(
+        &mut self,
+        ctx: &mut Context,
+        format: graphics::ImageFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> graphics::Image {
+        self.attachment(ctx, format, width, height, sample_count)
+    }
+
+    fn attachment(
+        &mut self,
+        ctx: &mut Context,
+        format: graphics::ImageFormat,
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> graphics::Image {
+        let tick = ctx.time.ticks();
+        // Sizes nobody has drawn at for a few frames are most likely from before a resize
+        self.attachments
+            .retain(|_, (_, last_used)| tick - *last_used <= ATTACHMENT_LIFETIME);
+        let (image, last_used) = self
+            .attachments
+            .entry((format, width, height, sample_count))
+            .or_insert_with(|| {
+                let image =
+                    graphics::Image::new_canvas_image(ctx, format, width, height, sample_count);
+                (image, tick)
+            });
+        *last_used = tick;
+        image.clone()
+    }
+
+    /// Whether images of `format` can be drawn to with `sample_count` samples per pixel.
+    /// Only the counts wgpu guarantees for the format are accepted, which for the formats
+    /// used here is 1 and 4. ggez neither exposes its adapter nor enables
+    /// [`wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES`], so the device rejects
+    /// counts like 8 even on adapters that support them.
+    pub fn sample_count_supported(
+        ctx: &Context,
+        format: graphics::ImageFormat,
+        sample_count: u32,
+    ) -> bool {
+        format
+            .guaranteed_format_features(ctx.gfx.wgpu().device.features())
+            .flags
+            .sample_count_supported(sample_count)
+    }
+
+    /// Returns order independent transparency targets of the given size, shared like depth images
+    pub fn oit_targets(&mut self, ctx: &mut Context, width: u32, height: u32) -> OitTargets {
+        let tick = ctx.time.ticks();
+        self.oit_targets
+            .retain(|_, (_, last_used)| tick - *last_used <= ATTACHMENT_LIFETIME);
+        let layout = &self.oit_bind_group_layout;
+        let (targets, last_used) = self.oit_targets.entry((width, height)).or_insert_with(|| {
+            let accum = graphics::Image::new_canvas_image(ctx, OIT_ACCUM_FORMAT, width, height, 1);
+            let reveal =
+                graphics::Image::new_canvas_image(ctx, OIT_REVEAL_FORMAT, width, height, 1);
+            let bind_group = ctx
+                .gfx
+                .wgpu()
+                .device
+                .create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("oit_bind_group"),
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(accum.wgpu().1),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(reveal.wgpu().1),
+                        },
+                    ],
+                });
+            let targets = OitTargets {
+                accum,
+                reveal,
+                bind_group: Arc::new(bind_group),
+            };
+            (targets, tick)
+        });
+        *last_used = tick;
+        targets.clone()
+    }
+
+    /// Builds the pipeline compositing order independent transparency onto `format` targets
+    pub fn update_oit_resolve_pipeline(
+        &mut self,
+        ctx: &mut Context,
+        format: graphics::ImageFormat,
+    ) {
+        if self.oit_resolve_pipelines.contains_key(&format) {
+            return;
+        }
+        let pipeline =
+            ctx.gfx
+                .wgpu()
+                .device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("OIT Resolve Pipeline"),
+                    layout: Some(&self.oit_resolve_layout),
+                    vertex: wgpu::VertexState {
+                        module: &self.oit_resolve_shader,
+                        entry_point: "vs_main",
+                        buffers: &[],
+                    },
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    fragment: Some(wgpu::FragmentState {
+                        module: &self.oit_resolve_shader,
+                        entry_point: "fs_main",
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format,
+                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                    }),
+                    multiview: None,
+                });
+        self.oit_resolve_pipelines.insert(format, pipeline);
+    }
+
+    /// The resolve pipeline for `format`, built by [`Renderer3d::update_oit_resolve_pipeline`]
+    pub fn oit_resolve_pipeline(&self, format: graphics::ImageFormat) -> &wgpu::RenderPipeline {
+        &self.oit_resolve_pipelines[&format]
+    }
+
+    /// Copies `image` back from the GPU as tightly packed RGBA8 rows.
+    /// Only submitted work can be read, so this fails inside a frame.
+    pub fn read_pixels(ctx: &mut Context, image: &graphics::Image) -> GameResult<Vec<u8>> {
+        let swizzle = match image.format() {
+            graphics::ImageFormat::Rgba8Unorm | graphics::ImageFormat::Rgba8UnormSrgb => false,
+            graphics::ImageFormat::Bgra8Unorm | graphics::ImageFormat::Bgra8UnormSrgb => true,
+            format => {
+                return Err(GameError::RenderError(format!(
+                    "cannot read back pixels of a {format:?} image"
+                )))
+            }
+        };
+        let mut pixels = Self::read_texture(ctx, image, wgpu::TextureAspect::All, 4)?;
+        if swizzle {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(pixels)
+    }
+
+    /// Copies a `Depth32Float` image back from the GPU, one value per pixel in rows.
+    /// Fails inside a frame like [`Renderer3d::read_pixels`].
+    pub fn read_depth(ctx: &mut Context, image: &graphics::Image) -> GameResult<Vec<f32>> {
+        if image.format() != graphics::ImageFormat::Depth32Float {
+            return Err(GameError::RenderError(format!(
+                "cannot read back depth of a {:?} image",
+                image.format()
+            )));
+        }
+        let bytes = Self::read_texture(ctx, image, wgpu::TextureAspect::DepthOnly, 4)?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|value| f32::from_ne_bytes([value[0], value[1], value[2], value[3]]))
+            .collect())
+    }
+
+    fn read_texture(
+        ctx: &mut Context,
+        image: &graphics::Image,
+        aspect: wgpu::TextureAspect,
+        block_size: u32,
+    ) -> GameResult<Vec<u8>> {
+        // The frame's commands are only submitted when it ends, reading now would see the last one
+        if ctx.gfx.commands().is_some() {
+            return Err(GameError::RenderError(String::from(
+                "cannot read back an image inside a frame, its drawing isn't submitted yet",
+            )));
+        }
+        if image.samples() > 1 {
+            return Err(GameError::RenderError(String::from(
+                "cannot read back a multisampled image",
+            )));
+        }
+        let wgpu = ctx.gfx.wgpu();
+        let row_size = block_size * image.width();
+        // Copies need rows aligned to 256 bytes, the padding is stripped again below
+        let padded_row_size = wgpu::util::align_to(row_size, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = wgpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: u64::from(padded_row_size) * u64::from(image.height()),
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = wgpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                aspect,
+                ..image.wgpu().0.as_image_copy()
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_size),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: image.width(),
+                height: image.height(),
+                depth_or_array_layers: 1,
+            },
+        );
+        wgpu.queue.submit([encoder.finish()]);
+
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = tx.send(result);
+            });
+        wgpu.device.poll(wgpu::Maintain::Wait);
+        rx.recv()
+            .map_err(|_| GameError::RenderError(String::from("readback buffer was never mapped")))?
+            .map_err(GameError::BufferAsyncError)?;
+
+        let data = buffer.slice(..).get_mapped_range();
+        let mut out = Vec::with_capacity((row_size * image.height()) as usize);
+        for row in data.chunks_exact(padded_row_size as usize) {
+            out.extend_from_slice(&row[..row_size as usize]);
+        }
+        Ok(out)
+    }
+
+    /// Starts handing out buffer space from the beginning again once the last frame is done.
+    /// Outside of a frame every canvas is submitted on its own, so each can start over.
+    fn claim_frame(&mut self, ctx: &mut Context) {
+        let tick = ctx.time.ticks();
+        if tick != self.frame_tick || ctx.gfx.commands().is_none() {
+            self.frame_tick = tick;
+            self.camera_count = 0;
+            self.instance_count = 0;
+        }
+    }
+
+    fn create_camera_buffer(ctx: &mut Context, stride: u64, capacity: usize) -> wgpu::Buffer {
+        ctx.gfx
+            .wgpu()
+            .device
+            .create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Camera Buffer"),
+                size: stride * capacity as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+    }
+
+    fn create_camera_bind_group(
+        ctx: &mut Context,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        ctx.gfx
+            .wgpu()
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<CameraUniform>() as u64),
+                    }),
+                }],
+                label: Some("camera_bind_group"),
+            })
+    }
+
+    fn create_instance_buffer(ctx: &mut Context, capacity: usize) -> wgpu::Buffer {
+        ctx.gfx
+            .wgpu()
+            .device
+            .create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Instance Buffer"),
+                size: (capacity * std::mem::size_of::<Instance3d>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+    }
+
+    /// Uploads the instances of one canvas after those of the canvases before it this frame,
+    /// returning the index of the first one in `instance_buffer`
+    pub fn update_instance_data(&mut self, ctx: &mut Context, instance_data: &[Instance3d]) -> u32 {
+        self.claim_frame(ctx);
+        // Grow in powers of two so the buffer is only reallocated a handful of times and then reused.
+        // Earlier canvases keep drawing from the old buffer, which lives as long as their commands.
+        let needed = self.instance_count + instance_data.len();
+        if needed > self.instance_capacity {
+            self.instance_capacity = needed.next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(ctx, self.instance_capacity);
+            self.instance_count = 0;
+        }
+        let first = self.instance_count;
+        ctx.gfx.wgpu().queue.write_buffer(
+            &self.instance_buffer,
+            (first * std::mem::size_of::<Instance3d>()) as wgpu::BufferAddress,
+            bytemuck::cast_slice(instance_data),
+        );
+        self.instance_count += instance_data.len();
+        first as u32
+    }
+
+    /// Fits `camera`'s projection to a `width` by `height` target. Nothing is uploaded,
+    /// each canvas uploads its camera when it's created.
+    pub fn resize(&mut self, width: f32, height: f32, camera: &mut CameraBundle) {
+        camera.projection.resize(width as u32, height as u32);
+    }
+
+    /// Uploads the camera of one canvas next to those of the canvases before it this frame,
+    /// returning the dynamic offset to bind `camera_bind_group` at
+    pub fn update_camera(&mut self, ctx: &mut Context, camera: &CameraBundle) -> u32 {
+        self.claim_frame(ctx);
+        if self.camera_count == self.camera_capacity {
+            self.camera_capacity *= 2;
+            self.camera_buffer =
+                Self::create_camera_buffer(ctx, self.camera_stride, self.camera_capacity);
+            self.camera_bind_group = Self::create_camera_bind_group(
+                ctx,
+                &self.camera_bind_group_layout,
+                &self.camera_buffer,
+            );
+            self.camera_count = 0;
+        }
+        let offset = self.camera_count as u64 * self.camera_stride;
+        self.camera_uniform.update_view_proj(camera);
+        ctx.gfx.wgpu().queue.write_buffer(
+            &self.camera_buffer,
+            offset,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+        self.camera_count += 1;
+        offset as u32
+    }
+}
@@ -0,0 +1,358 @@
+use glam::Vec3;
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+use crate::mesh::{Mesh3d, Vertex};
+
+// Every shape is centered on the origin with Y up, wound counter clockwise seen from outside
+// and textured with (0, 0) at the top left of the image.
+impl Mesh3d {
+    /// A cube with edges `size` long, each face showing the whole texture
+    pub fn cube(size: f32) -> Self {
+        let half = size / 2.0;
+        // Outward normal, then right and up as seen looking at that face
+        let faces = [
+            (Vec3::X, Vec3::NEG_Z, Vec3::Y),
+            (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+            (Vec3::Y, Vec3::X, Vec3::NEG_Z),
+            (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+            (Vec3::Z, Vec3::X, Vec3::Y),
+            (Vec3::NEG_Z, Vec3::NEG_X, Vec3::Y),
+        ];
+        let mut vertices = Vec::with_capacity(24);
+        let mut indices = Vec::with_capacity(36);
+        for (normal, right, up) in faces {
+            let start = vertices.len() as u32;
+            for (x, y, uv) in [
+                (-1.0, -1.0, [0.0, 1.0]),
+                (1.0, -1.0, [1.0, 1.0]),
+                (1.0, 1.0, [1.0, 0.0]),
+                (-1.0, 1.0, [0.0, 0.0]),
+            ] {
+                let position = (normal + right * x + up * y) * half;
+                vertices.push(Vertex::new(position, uv, None).normal(normal));
+            }
+            indices.extend([0, 1, 2, 2, 3, 0].map(|i| start + i));
+        }
+        Self::from_parts(vertices, indices)
+    }
+
+    /// A flat square `size` wide facing up, split into `subdivisions + 1` quads along each side
+    pub fn plane(size: f32, subdivisions: u32) -> Self {
+        let segments = subdivisions + 1;
+        let (vertices, indices) = grid(segments, segments, |s, t| {
+            let position = Vec3::new((s - 0.5) * size, 0.0, (0.5 - t) * size);
+            Vertex::new(position, [s, 1.0 - t], None).normal(Vec3::Y)
+        });
+        Self::from_parts(vertices, indices)
+    }
+
+    /// A sphere of `sectors` slices around and `stacks` rings from pole to pole
+    pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> Self {
+        let (vertices, indices) = grid(sectors.max(3), stacks.max(2), |s, t| {
+            let normal = sphere_normal(s * TAU, (t - 0.5) * PI);
+            Vertex::new(normal * radius, [s, 1.0 - t], None).normal(normal)
+        });
+        Self::from_parts(vertices, indices)
+    }
+
+    /// A sphere made by splitting an icosahedron's triangles `subdivisions` times,
+    /// which spreads vertices more evenly than [`Mesh3d::uv_sphere`]
+    pub fn icosphere(radius: f32, subdivisions: u32) -> Self {
+        // A vertex on each pole and rings of five above and below the equator, turned so that
+        // none of them lands on the seam at longitude 0
+        let ring = 0.5_f32.atan();
+        let mut positions = vec![Vec3::Y];
+        positions.extend((0..5).map(|i| sphere_normal((i as f32 + 0.25) * TAU / 5.0, ring)));
+        positions.extend((0..5).map(|i| sphere_normal((i as f32 + 0.75) * TAU / 5.0, -ring)));
+        positions.push(Vec3::NEG_Y);
+        let mut triangles: Vec<[u32; 3]> = (0..5)
+            .flat_map(|i| {
+                let (upper, lower) = (1 + i, 6 + i);
+                let (next_upper, next_lower) = (1 + (i + 1) % 5, 6 + (i + 1) % 5);
+                [
+                    [0, upper, next_upper],
+                    [upper, lower, next_upper],
+                    [next_upper, lower, next_lower],
+                    [11, next_lower, lower],
+                ]
+            })
+            .collect();
+
+        for _ in 0..subdivisions {
+            let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+            let mut midpoint = |a: u32, b: u32| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    positions.push((positions[a as usize] + positions[b as usize]).normalize());
+                    positions.len() as u32 - 1
+                })
+            };
+            triangles = triangles
+                .into_iter()
+                .flat_map(|[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        // The seam, where u wraps from 1 back to 0, is the half plane x = 0 in front of the
+        // sphere. Triangles touching it are cut in two along it so that each side runs up to its
+        // own edge of the texture: left of it, with x below 0, u is close to 1, right of it
+        // close to 0. Vertices on the seam and where edges cross it are used by both sides,
+        // with u at 1 on one and 0 on the other. Edges are cut once, so the triangles on
+        // either side of one share the vertex it's cut at. The poles have no longitude of
+        // their own and take the mean u of the rest of the piece they're in.
+        const EPSILON: f32 = 1e-6;
+        let side = |p: Vec3| {
+            if p.x.abs() < EPSILON && p.z > -EPSILON {
+                0.0
+            } else {
+                p.x.signum()
+            }
+        };
+        let mut crossings: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut pieces: Vec<[(u32, f32); 3]> = Vec::with_capacity(triangles.len());
+        for triangle in triangles {
+            let mut whole = Vec::with_capacity(3);
+            let mut left = Vec::with_capacity(4);
+            let mut right = Vec::with_capacity(4);
+            let mut cut = false;
+            for k in 0..3 {
+                let (a, b) = (triangle[k], triangle[(k + 1) % 3]);
+                let (pa, pb) = (positions[a as usize], positions[b as usize]);
+                if pa.x.abs() < EPSILON && pa.z.abs() < EPSILON {
+                    whole.push((a, None));
+                    left.push((a, None));
+                    right.push((a, None));
+                } else if side(pa) == 0.0 {
+                    cut = true;
+                    left.push((a, Some(1.0)));
+                    right.push((a, Some(0.0)));
+                } else {
+                    let u = sphere_uv(pa)[0];
+                    whole.push((a, Some(u)));
+                    if pa.x < 0.0 {
+                        left.push((a, Some(u)));
+                    } else {
+                        right.push((a, Some(u)));
+                    }
+                }
+                if side(pa) * side(pb) < 0.0 {
+                    let key = (a.min(b), a.max(b));
+                    let crossing = match crossings.get(&key) {
+                        Some(&crossing) => Some(crossing),
+                        None => {
+                            let point = pa.lerp(pb, pa.x / (pa.x - pb.x));
+                            // Behind the sphere u goes on smoothly through 0.5
+                            (point.z > 0.0).then(|| {
+                                positions.push(point.normalize());
+                                crossings.insert(key, positions.len() as u32 - 1);
+                                positions.len() as u32 - 1
+                            })
+                        }
+                    };
+                    if let Some(crossing) = crossing {
+                        cut = true;
+                        left.push((crossing, Some(1.0)));
+                        right.push((crossing, Some(0.0)));
+                    }
+                }
+            }
+            // Both sides are convex and keep the triangle's winding, so they can be fanned
+            let sides = if cut { vec![left, right] } else { vec![whole] };
+            for side in sides.into_iter().filter(|side| side.len() >= 3) {
+                let known = side.iter().filter_map(|&(_, u)| u).collect::<Vec<_>>();
+                let pole = known.iter().sum::<f32>() / known.len() as f32;
+                let side = side
+                    .into_iter()
+                    .map(|(i, u)| (i, u.unwrap_or(pole)))
+                    .collect::<Vec<_>>();
+                for k in 1..side.len() - 1 {
+                    pieces.push([side[0], side[k], side[k + 1]]);
+                }
+            }
+        }
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::with_capacity(pieces.len() * 3);
+        let mut lookup: HashMap<(u32, u32), u32> = HashMap::new();
+        for (i, u) in pieces.into_iter().flatten() {
+            let index = *lookup.entry((i, u.to_bits())).or_insert_with(|| {
+                let normal = positions[i as usize];
+                let uv = [u, sphere_uv(normal)[1]];
+                vertices.push(Vertex::new(normal * radius, uv, None).normal(normal));
+                vertices.len() as u32 - 1
+            });
+            indices.push(index);
+        }
+        Self::from_parts(vertices, indices)
+    }
+
+    /// An upright cylinder `height` tall with capped ends
+    pub fn cylinder(radius: f32, height: f32, sectors: u32) -> Self {
+        let sectors = sectors.max(3);
+        let (mut vertices, mut indices) = grid(sectors, 1, |s, t| {
+            let normal = sphere_normal(s * TAU, 0.0);
+            let position = normal * radius + Vec3::Y * (t - 0.5) * height;
+            Vertex::new(position, [s, 1.0 - t], None).normal(normal)
+        });
+        disc(
+            &mut vertices,
+            &mut indices,
+            radius,
+            height / 2.0,
+            sectors,
+            true,
+        );
+        disc(
+            &mut vertices,
+            &mut indices,
+            radius,
+            -height / 2.0,
+            sectors,
+            false,
+        );
+        Self::from_parts(vertices, indices)
+    }
+
+    /// An upright cone `height` tall with its point at the top and a capped base
+    pub fn cone(radius: f32, height: f32, sectors: u32) -> Self {
+        let sectors = sectors.max(3);
+        // Each slice gets its own tip so the sides shade smoothly up to the point
+        let (mut vertices, mut indices) = grid(sectors, 1, |s, t| {
+            let around = sphere_normal(s * TAU, 0.0);
+            let normal = (around * height + Vec3::Y * radius).normalize();
+            let position = around * radius * (1.0 - t) + Vec3::Y * (t - 0.5) * height;
+            Vertex::new(position, [s, 1.0 - t], None).normal(normal)
+        });
+        disc(
+            &mut vertices,
+            &mut indices,
+            radius,
+            -height / 2.0,
+            sectors,
+            false,
+        );
+        Self::from_parts(vertices, indices)
+    }
+
+    /// A cylinder `length` long between two hemispheres, `stacks` rings each
+    pub fn capsule(radius: f32, length: f32, sectors: u32, stacks: u32) -> Self {
+        let stacks = stacks.max(1);
+        // Latitude of each ring and the height of its hemisphere's center,
+        // the equator appears once for each hemisphere which makes the cylinder between them
+        let rings = (0..=stacks)
+            .map(|i| ((i as f32 / stacks as f32 - 1.0) * PI / 2.0, -length / 2.0))
+            .chain((0..=stacks).map(|i| (i as f32 / stacks as f32 * PI / 2.0, length / 2.0)))
+            .collect::<Vec<_>>();
+        // Texture runs along the outline, from the bottom pole to the top one
+        let outline = PI * radius + length;
+        let (vertices, indices) = grid(sectors.max(3), rings.len() as u32 - 1, |s, t| {
+            let ring = (t * (rings.len() - 1) as f32).round() as usize;
+            let (latitude, offset) = rings[ring];
+            let normal = sphere_normal(s * TAU, latitude);
+            let position = normal * radius + Vec3::Y * offset;
+            let travelled = (latitude + PI / 2.0) * radius + offset + length / 2.0;
+            Vertex::new(position, [s, 1.0 - travelled / outline], None).normal(normal)
+        });
+        Self::from_parts(vertices, indices)
+    }
+
+    /// A ring lying flat, `major_radius` to the middle of a tube `minor_radius` thick
+    pub fn torus(
+        major_radius: f32,
+        minor_radius: f32,
+        major_segments: u32,
+        minor_segments: u32,
+    ) -> Self {
+        let (vertices, indices) = grid(major_segments.max(3), minor_segments.max(3), |s, t| {
+            let outward = sphere_normal(s * TAU, 0.0);
+            let (sin, cos) = (t * TAU).sin_cos();
+            let normal = outward * cos + Vec3::Y * sin;
+            let position = outward * major_radius + normal * minor_radius;
+            Vertex::new(position, [s, 1.0 - t], None).normal(normal)
+        });
+        Self::from_parts(vertices, indices)
+    }
+
+    fn from_parts(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+        Mesh3d {
+            vertices,
+            indices,
+            ..Default::default()
+        }
+    }
+}
+
+/// Unit vector `longitude` around the Y axis starting from +Z, `latitude` up from the equator
+fn sphere_normal(longitude: f32, latitude: f32) -> Vec3 {
+    let (sin_lon, cos_lon) = longitude.sin_cos();
+    let (sin_lat, cos_lat) = latitude.sin_cos();
+    Vec3::new(cos_lat * sin_lon, sin_lat, cos_lat * cos_lon)
+}
+
+/// The inverse of [`sphere_normal`] mapped to texture coordinates
+fn sphere_uv(normal: Vec3) -> [f32; 2] {
+    [
+        (normal.x.atan2(normal.z) / TAU).rem_euclid(1.0),
+        0.5 - normal.y.clamp(-1.0, 1.0).asin() / PI,
+    ]
+}
+
+/// Builds a `columns` by `rows` grid of quads from `vertex(s, t)`, with `s` and `t` going from
+/// 0 to 1. The surface faces the way of `ds x dt`. Triangles collapsed to a point or a line,
+/// like those touching a pole, are left out.
+fn grid(columns: u32, rows: u32, vertex: impl Fn(f32, f32) -> Vertex) -> (Vec<Vertex>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(((columns + 1) * (rows + 1)) as usize);
+    for j in 0..=rows {
+        for i in 0..=columns {
+            vertices.push(vertex(i as f32 / columns as f32, j as f32 / rows as f32));
+        }
+    }
+
+    let mut indices = Vec::with_capacity((columns * rows * 6) as usize);
+    let index = |i: u32, j: u32| j * (columns + 1) + i;
+    for j in 0..rows {
+        for i in 0..columns {
+            let (a, b) = (index(i, j), index(i + 1, j));
+            let (c, d) = (index(i + 1, j + 1), index(i, j + 1));
+            for triangle in [[a, b, c], [c, d, a]] {
+                let [p0, p1, p2] = triangle.map(|i| Vec3::from(vertices[i as usize].pos));
+                if (p1 - p0).cross(p2 - p0).length_squared() > f32::EPSILON * f32::EPSILON {
+                    indices.extend(triangle);
+                }
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+/// Adds a flat cap at `height` facing up, or down when `up` is false
+fn disc(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut Vec<u32>,
+    radius: f32,
+    height: f32,
+    sectors: u32,
+    up: bool,
+) {
+    let normal = if up { Vec3::Y } else { Vec3::NEG_Y };
+    // Seen from below the top's mapping would come out mirrored
+    let flip = if up { 1.0 } else { -1.0 };
+    let center = vertices.len() as u32;
+    vertices.push(Vertex::new(Vec3::Y * height, [0.5, 0.5], None).normal(normal));
+    for i in 0..=sectors {
+        let around = sphere_normal(i as f32 / sectors as f32 * TAU, 0.0);
+        let uv = [0.5 + around.x * 0.5, 0.5 + around.z * 0.5 * flip];
+        vertices.push(Vertex::new(around * radius + Vec3::Y * height, uv, None).normal(normal));
+    }
+    for i in 0..sectors {
+        let (a, b) = (center + 1 + i, center + 2 + i);
+        if up {
+            indices.extend([center, a, b]);
+        } else {
+            indices.extend([center, b, a]);
+        }
+    }
+}
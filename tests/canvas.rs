@@ -0,0 +1,26 @@
+use ggez::glam::{Mat4, Quat, Vec3};
+use ggez_3d::prelude::*;
+
+fn moved(instance: &Instance3d, point: Vec3) -> Vec3 {
+    Mat4::from_cols_array_2d(&instance.transform).transform_point3(point)
+}
+
+#[test]
+fn param_translates_and_rotates_around_center() {
+    let center = Vec3::new(0.0, 1.0, 0.0);
+    let instance =
+        Instance3d::from_param(&DrawParam3d::default().position([1.0, 2.0, 3.0]), center);
+    assert!(moved(&instance, center).abs_diff_eq(Vec3::new(1.0, 3.0, 3.0), 1e-6));
+
+    let instance = Instance3d::from_param(
+        &DrawParam3d::default()
+            .position([1.0, 2.0, 3.0])
+            .rotation(Quat::from_rotation_y(1.0))
+            .scale([2.0, 2.0, 2.0]),
+        center,
+    );
+    // The center stays put relative to the position, everything else turns around it
+    assert!(moved(&instance, center).abs_diff_eq(Vec3::new(1.0, 3.0, 3.0), 1e-6));
+    let corner = moved(&instance, center + Vec3::X);
+    assert!((corner.distance(Vec3::new(1.0, 3.0, 3.0)) - 2.0).abs() < 1e-5);
+}
@@ -0,0 +1,188 @@
+use ggez::glam::Vec3;
+use ggez::graphics::{self, Color};
+use ggez_3d::formats::{gltf, obj};
+use ggez_3d::prelude::*;
+
+/// Every vertex a mesh draws, in order, since importers may share or reorder vertices
+fn corners(mesh: &Mesh3d) -> Vec<Vertex> {
+    mesh.indices
+        .iter()
+        .map(|&i| mesh.vertices[i as usize])
+        .collect()
+}
+
+fn assert_same_vertices(expected: &Mesh3d, actual: &Mesh3d) {
+    let (expected, actual) = (corners(expected), corners(actual));
+    assert_eq!(expected.len(), actual.len());
+    for (i, (e, a)) in expected.iter().zip(actual.iter()).enumerate() {
+        assert_eq!(e.pos, a.pos, "corner {i}");
+        assert_eq!(e.normal, a.normal, "corner {i}");
+        assert_eq!(e.color, a.color, "corner {i}");
+        // OBJ flips v, which can move it by a rounding error
+        assert!(
+            (Vec3::new(e.tex_coord[0], e.tex_coord[1], 0.0)
+                - Vec3::new(a.tex_coord[0], a.tex_coord[1], 0.0))
+            .length()
+                < 1e-6,
+            "corner {i} has uv {:?}, expected {:?}",
+            a.tex_coord,
+            e.tex_coord
+        );
+    }
+}
+
+/// A sphere with colors running from bottom to top
+fn colored_sphere() -> Mesh3d {
+    let mut mesh = Mesh3d::uv_sphere(1.5, 12, 6);
+    for vertex in mesh.vertices.iter_mut() {
+        let t = vertex.pos[1] / 3.0 + 0.5;
+        vertex.color = Color::new(t, 0.5, 1.0 - t, 1.0).into();
+    }
+    mesh
+}
+
+fn parse_obj(source: &str) -> Mesh3d {
+    let data = obj::parse(source).unwrap();
+    assert_eq!(data.groups.len(), 1);
+    let group = data.groups.into_iter().next().unwrap();
+    Mesh3d {
+        vertices: group.vertices,
+        indices: group.indices,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn obj_round_trips() {
+    for mesh in [
+        Mesh3d::cube(2.0),
+        Mesh3d::torus(1.0, 0.25, 16, 8),
+        colored_sphere(),
+    ] {
+        let source = obj::write(&mesh, None).unwrap();
+        assert_same_vertices(&mesh, &parse_obj(&source));
+    }
+}
+
+#[test]
+fn obj_round_trips_texture() {
+    let mesh = Mesh3d::plane(2.0, 2);
+    let source = obj::write(&mesh, Some("plane.mtl")).unwrap();
+    let data = obj::parse(&source).unwrap();
+    assert_eq!(data.material_libs, ["plane.mtl"]);
+    let materials = obj::parse_mtl(&obj::write_mtl("textures/grid.png").unwrap()).unwrap();
+    assert_eq!(materials.len(), 1);
+    assert_eq!(data.groups[0].material.as_ref(), Some(&materials[0].name));
+    assert_eq!(
+        materials[0].diffuse_texture.as_deref(),
+        Some("textures/grid.png")
+    );
+    assert_same_vertices(&mesh, &parse_obj(&source));
+
+    assert!(obj::write_mtl("my textures/grid.png").is_err());
+}
+
+#[test]
+fn obj_rejects_other_topologies() {
+    let mesh = Mesh3d {
+        topology: wgpu::PrimitiveTopology::TriangleStrip,
+        ..Mesh3d::cube(2.0)
+    };
+    assert!(obj::write(&mesh, None).is_err());
+}
+
+/// Parses a `.glb` without external buffers
+fn parse_glb(bytes: &[u8]) -> gltf::GltfData {
+    gltf::parse(bytes, |uri| panic!("read external buffer {uri}")).unwrap()
+}
+
+fn only_primitive(data: &gltf::GltfData) -> &gltf::GltfPrimitive {
+    let scene = &data.scene;
+    assert_eq!(scene.roots, [0]);
+    assert_eq!(scene.nodes[0].mesh, Some(0));
+    assert_eq!(scene.meshes.len(), 1);
+    assert_eq!(scene.meshes[0].len(), 1);
+    &scene.meshes[0][0]
+}
+
+#[test]
+fn glb_round_trips() {
+    for mesh in [
+        Mesh3d::cube(2.0),
+        Mesh3d::torus(1.0, 0.25, 16, 8),
+        colored_sphere(),
+    ] {
+        let data = parse_glb(&gltf::write_glb(&mesh, None).unwrap());
+        let primitive = only_primitive(&data);
+        assert_eq!(primitive.mesh.topology, mesh.topology);
+        // Exact, glTF keeps the vertices and indices as they are
+        assert_eq!(primitive.mesh.indices, mesh.indices);
+        assert_same_vertices(&mesh, &primitive.mesh);
+        assert_eq!(primitive.material.base_color_texture, None);
+    }
+}
+
+#[test]
+fn glb_round_trips_texture() {
+    let mesh = Mesh3d {
+        sampler: graphics::Sampler::nearest_clamp(),
+        ..Mesh3d::cube(1.0)
+    };
+    let data = parse_glb(&gltf::write_glb(&mesh, Some("textures/crate wood.png")).unwrap());
+    let primitive = only_primitive(&data);
+    assert_same_vertices(&mesh, &primitive.mesh);
+    assert_eq!(primitive.mesh.sampler, mesh.sampler);
+    let image = primitive.material.base_color_texture.unwrap();
+    assert_eq!(
+        data.images[image],
+        gltf::GltfImage::Uri(String::from("textures/crate wood.png"))
+    );
+}
+
+#[test]
+fn glb_round_trips_points() {
+    let mesh = Mesh3d {
+        vertices: (0..4)
+            .map(|i| Vertex::new([i as f32, 0.0, 0.0], [0.0, 0.0], None))
+            .collect(),
+        indices: vec![0, 1, 2, 3],
+        topology: wgpu::PrimitiveTopology::PointList,
+        ..Default::default()
+    };
+    let data = parse_glb(&gltf::write_glb(&mesh, None).unwrap());
+    let primitive = only_primitive(&data);
+    assert_eq!(primitive.mesh.topology, wgpu::PrimitiveTopology::PointList);
+    assert_same_vertices(&mesh, &primitive.mesh);
+
+    assert!(gltf::write_glb(&Mesh3d::default(), None).is_err());
+}
+
+#[test]
+fn zero_normals_are_left_out() {
+    let mut mesh = Mesh3d::cube(2.0);
+    mesh.vertices[0].normal = [0.0; 3];
+
+    let source = obj::write(&mesh, None).unwrap();
+    assert!(!source.contains("vn "));
+    assert!(source.lines().any(|line| line == "f 1/1 2/2 3/3"));
+    let loaded = parse_obj(&source);
+    assert!(corners(&loaded).iter().all(|v| v.normal == [0.0; 3]));
+
+    let data = parse_glb(&gltf::write_glb(&mesh, None).unwrap());
+    let primitive = only_primitive(&data);
+    assert!(primitive.mesh.vertices.iter().all(|v| v.normal == [0.0; 3]));
+
+    // Other attributes still make it
+    mesh.vertices.iter_mut().for_each(|v| v.normal = [0.0; 3]);
+    assert_same_vertices(&mesh, &primitive.mesh);
+    assert_same_vertices(&mesh, &loaded);
+}
+
+#[test]
+fn glb_rejects_non_finite_positions() {
+    let mut mesh = Mesh3d::cube(2.0);
+    mesh.vertices[3].pos[1] = f32::NAN;
+    assert!(gltf::write_glb(&mesh, None).is_err());
+    mesh.vertices[3].pos[1] = f32::INFINITY;
+    assert!(gltf::write_glb(&mesh, None).is_err());
+}
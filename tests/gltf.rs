@@ -0,0 +1,192 @@
+use base64::Engine;
+use ggez::glam::{Mat4, Vec3};
+use ggez_3d::formats::gltf;
+
+/// Positions of one triangle, the only buffer of every test file
+fn triangle() -> Vec<u8> {
+    [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+        .iter()
+        .flatten()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn data_uri() -> String {
+    format!(
+        "data:application/octet-stream;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(triangle())
+    )
+}
+
+const ONE_MESH: &str = r#"[{"primitives":[{"attributes":{"POSITION":0}}]}]"#;
+
+/// A `.gltf` file drawing the triangle buffer at `uri` with `meshes`, `nodes` and scene `roots`
+fn document(uri: &str, meshes: &str, nodes: &str, roots: &str, extra: &str) -> String {
+    format!(
+        r#"{{
+            "asset": {{"version": "2.0"}},
+            "buffers": [{{"uri": "{uri}", "byteLength": 36}}],
+            "bufferViews": [{{"buffer": 0, "byteLength": 36}}],
+            "accessors": [{{
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]
+            }}],
+            "materials": [
+                {{"pbrMetallicRoughness": {{"baseColorFactor": [1, 0, 0, 1]}}}},
+                {{"pbrMetallicRoughness": {{"baseColorFactor": [0, 0, 1, 1]}}}}
+            ],
+            "meshes": {meshes},
+            "nodes": {nodes},
+            "scenes": [{{"nodes": {roots}}}]
+            {extra}
+        }}"#
+    )
+}
+
+fn parse(json: &str) -> ggez::GameResult<gltf::GltfData> {
+    gltf::parse(json.as_bytes(), |uri| panic!("read external buffer {uri}"))
+}
+
+fn positions(primitive: &gltf::GltfPrimitive) -> Vec<[f32; 3]> {
+    primitive
+        .mesh
+        .vertices
+        .iter()
+        .map(|vertex| vertex.pos)
+        .collect()
+}
+
+#[test]
+fn reads_external_buffers() {
+    let json = document(
+        "buffers/tri%20angle.bin",
+        ONE_MESH,
+        r#"[{"mesh": 0}]"#,
+        "[0]",
+        "",
+    );
+    let mut read = Vec::new();
+    let data = gltf::parse(json.as_bytes(), |uri| {
+        read.push(String::from(uri));
+        Ok(triangle())
+    })
+    .unwrap();
+    assert_eq!(read, ["buffers/tri angle.bin"]);
+    let primitive = &data.scene.meshes[0][0];
+    assert_eq!(
+        positions(primitive),
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+    );
+    assert_eq!(primitive.mesh.indices, [0, 1, 2]);
+
+    // Shorter than the buffer claims
+    let short = gltf::parse(json.as_bytes(), |_| Ok(vec![0; 12]));
+    assert!(short.is_err());
+}
+
+#[test]
+fn reads_data_uris() {
+    let json = document(&data_uri(), ONE_MESH, r#"[{"mesh": 0}]"#, "[0]", "");
+    let data = parse(&json).unwrap();
+    assert_eq!(
+        positions(&data.scene.meshes[0][0]),
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+    );
+}
+
+#[test]
+fn rejects_required_extensions() {
+    let extensions = r#", "extensionsUsed": ["KHR_draco_mesh_compression"],
+        "extensionsRequired": ["KHR_draco_mesh_compression"]"#;
+    let json = document(&data_uri(), ONE_MESH, r#"[{"mesh": 0}]"#, "[0]", extensions);
+    let Err(ggez::GameError::ResourceLoadError(message)) = parse(&json) else {
+        panic!("loaded a file requiring an extension");
+    };
+    assert!(message.contains("KHR_draco_mesh_compression"), "{message}");
+
+    // Only using it is fine
+    let json = document(
+        &data_uri(),
+        ONE_MESH,
+        r#"[{"mesh": 0}]"#,
+        "[0]",
+        r#", "extensionsUsed": ["KHR_materials_emissive_strength"]"#,
+    );
+    assert!(parse(&json).is_ok());
+}
+
+#[test]
+fn splits_primitives() {
+    let meshes = r#"[{"primitives": [
+        {"attributes": {"POSITION": 0}, "material": 0},
+        {"attributes": {"POSITION": 0}, "material": 1, "mode": 0}
+    ]}]"#;
+    let data = parse(&document(
+        &data_uri(),
+        meshes,
+        r#"[{"mesh": 0}]"#,
+        "[0]",
+        "",
+    ))
+    .unwrap();
+    assert_eq!(data.scene.meshes.len(), 1);
+    let [red, blue] = &data.scene.meshes[0][..] else {
+        panic!("expected two primitives");
+    };
+    assert_eq!(red.material.base_color, [1.0, 0.0, 0.0, 1.0]);
+    assert_eq!(red.mesh.vertices[0].color, [1.0, 0.0, 0.0, 1.0]);
+    assert_eq!(red.mesh.topology, wgpu::PrimitiveTopology::TriangleList);
+    assert_eq!(blue.material.base_color, [0.0, 0.0, 1.0, 1.0]);
+    assert_eq!(blue.mesh.vertices[0].color, [0.0, 0.0, 1.0, 1.0]);
+    assert_eq!(blue.mesh.topology, wgpu::PrimitiveTopology::PointList);
+}
+
+#[test]
+fn composes_node_transforms() {
+    let nodes = r#"[
+        {"name": "root", "translation": [1, 0, 0], "children": [1]},
+        {"scale": [2, 2, 2], "children": [2]},
+        {"translation": [0, 1, 0], "mesh": 0},
+        {"translation": [5, 5, 5], "mesh": 0}
+    ]"#;
+    let data = parse(&document(&data_uri(), ONE_MESH, nodes, "[0]", "")).unwrap();
+    let scene = &data.scene;
+    assert_eq!(scene.roots, [0]);
+    assert_eq!(scene.nodes[0].name.as_deref(), Some("root"));
+    assert_eq!(scene.nodes[0].children, [1]);
+    assert_eq!(scene.nodes[2].mesh, Some(0));
+
+    let transforms = scene.world_transforms();
+    assert_eq!(transforms.len(), 4);
+    let origin = |node: usize| transforms[node].transform_point3(Vec3::ZERO);
+    assert!(origin(0).abs_diff_eq(Vec3::new(1.0, 0.0, 0.0), 1e-6));
+    assert!(origin(1).abs_diff_eq(Vec3::new(1.0, 0.0, 0.0), 1e-6));
+    // The parent's scale doubles the child's translation
+    assert!(origin(2).abs_diff_eq(Vec3::new(1.0, 2.0, 0.0), 1e-6));
+    assert!(transforms[2]
+        .transform_vector3(Vec3::X)
+        .abs_diff_eq(Vec3::new(2.0, 0.0, 0.0), 1e-6));
+    // Not part of the scene
+    assert_eq!(transforms[3], Mat4::IDENTITY);
+}
+
+#[test]
+fn rejects_node_cycles() {
+    for nodes in [
+        r#"[{"children": [0]}]"#,
+        r#"[{"children": [1]}, {"children": [2]}, {"children": [0]}]"#,
+        // Not reachable from the scene, still not a tree
+        r#"[{"mesh": 0}, {"children": [2]}, {"children": [1]}]"#,
+    ] {
+        let Err(ggez::GameError::ResourceLoadError(message)) =
+            parse(&document(&data_uri(), ONE_MESH, nodes, "[0]", ""))
+        else {
+            panic!("loaded the cyclic nodes {nodes}");
+        };
+        assert!(message.contains("its own descendant"), "{message}");
+    }
+
+    // Shared children aren't cycles
+    let nodes = r#"[{"children": [1, 2]}, {"children": [2]}, {"mesh": 0}]"#;
+    assert!(parse(&document(&data_uri(), ONE_MESH, nodes, "[0]", "")).is_ok());
+}
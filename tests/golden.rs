@@ -0,0 +1,351 @@
+//! Renders small scenes offscreen through [`Canvas3d`] and compares them to the reference PNGs
+//! in `tests/golden`.
+//!
+//! A display and a wgpu adapter are needed, a software one such as lavapipe works fine.
+//! On Debian and Ubuntu that is `apt install xvfb mesa-vulkan-drivers`, then
+//! `WGPU_BACKEND=vulkan xvfb-run -a cargo test --test golden`.
+//!
+//! Without a display every scene is reported as skipped and the run still passes, so CI has to
+//! set `GOLDEN_REQUIRE=1`, which turns skipping into a failure:
+//! `GOLDEN_REQUIRE=1 WGPU_BACKEND=vulkan xvfb-run -a cargo test --test golden`.
+//!
+//! A missing reference fails its scene. Run once with `GOLDEN_BLESS=1` on lavapipe to write
+//! all of them from the current output, and commit them along with the change that moved them.
+//! When a scene doesn't match, its output and a diff image are written to the `golden` folder
+//! of cargo's test temp dir.
+
+use std::path::{Path, PathBuf};
+
+use ggez::glam::*;
+use ggez::graphics::{self, BlendMode, Color};
+use ggez::{Context, GameResult};
+use ggez_3d::prelude::*;
+
+const SIZE: u32 = 128;
+
+/// Largest per channel difference that still counts as the same pixel
+const TOLERANCE: u8 = 8;
+
+/// Fraction of pixels allowed to differ, rasterization varies a little between adapters
+const MAX_MISMATCHED: f32 = 0.005;
+
+type Scene = fn(&mut Context, &mut Renderer3d) -> GameResult<Vec<u8>>;
+
+const SCENES: &[(&str, Scene)] = &[
+    ("cube", cube_scene),
+    ("depth", depth_scene),
+    ("transparent", transparent_scene),
+    ("oit", oit_scene),
+    ("msaa", msaa_scene),
+    ("layers", layers_scene),
+];
+
+fn main() {
+    if !has_display() {
+        skip("no display");
+    }
+    let cb = ggez::ContextBuilder::new("ggez_3d_golden", "ggez").window_mode(
+        ggez::conf::WindowMode::default()
+            .dimensions(SIZE as f32, SIZE as f32)
+            .visible(false),
+    );
+    let (mut ctx, _event_loop) = match cb.build() {
+        Ok(built) => built,
+        Err(e) => {
+            println!("golden: cannot create a context: {e}");
+            std::process::exit(1);
+        }
+    };
+    let mut renderer = Renderer3d::new(&mut ctx);
+    let bless = std::env::var_os("GOLDEN_BLESS").is_some();
+
+    let mut failed = 0;
+    for (name, scene) in SCENES {
+        let result = scene(&mut ctx, &mut renderer).and_then(|pixels| check(name, &pixels, bless));
+        match result {
+            Ok(()) => println!("golden {name} ... ok"),
+            Err(e) => {
+                println!("golden {name} ... FAILED: {e}");
+                failed += 1;
+            }
+        }
+    }
+    println!("golden: {} passed, {failed} failed", SCENES.len() - failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Reports every scene as skipped and exits, failing if the scenes are required to run
+fn skip(reason: &str) -> ! {
+    for (name, _) in SCENES {
+        println!("golden {name} ... skipped: {reason}");
+    }
+    if std::env::var_os("GOLDEN_REQUIRE").is_some() {
+        println!(
+            "golden: {} skipped, but GOLDEN_REQUIRE is set",
+            SCENES.len()
+        );
+        std::process::exit(1);
+    }
+    println!(
+        "golden: 0 passed, 0 failed, {} skipped, set GOLDEN_REQUIRE=1 to fail instead",
+        SCENES.len()
+    );
+    std::process::exit(0);
+}
+
+fn has_display() -> bool {
+    !cfg!(unix)
+        || cfg!(target_os = "macos")
+        || std::env::var_os("DISPLAY").is_some()
+        || std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+fn check(name: &str, pixels: &[u8], bless: bool) -> GameResult {
+    let reference_path = reference_path(name);
+    if bless {
+        std::fs::create_dir_all(reference_path.parent().unwrap())?;
+        save(&reference_path, pixels)?;
+        println!("golden {name}: wrote {}", reference_path.display());
+        return Ok(());
+    }
+    if !reference_path.exists() {
+        return Err(ggez::GameError::CustomError(format!(
+            "{} is missing, run with GOLDEN_BLESS=1 to write it",
+            reference_path.display()
+        )));
+    }
+    let reference = image::open(&reference_path)
+        .map_err(|e| ggez::GameError::CustomError(e.to_string()))?
+        .to_rgba8();
+    if reference.dimensions() != (SIZE, SIZE) {
+        return Err(ggez::GameError::CustomError(format!(
+            "reference is {:?} but the scene is {SIZE}x{SIZE}",
+            reference.dimensions()
+        )));
+    }
+
+    let mut diff = Vec::with_capacity(pixels.len());
+    let mut mismatched = 0;
+    for (actual, expected) in pixels
+        .chunks_exact(4)
+        .zip(reference.as_raw().chunks_exact(4))
+    {
+        let matches = actual
+            .iter()
+            .zip(expected)
+            .all(|(a, e)| a.abs_diff(*e) <= TOLERANCE);
+        if matches {
+            // Dimmed so the mismatches stand out
+            diff.extend(actual[..3].iter().map(|c| c / 4));
+            diff.push(255);
+        } else {
+            mismatched += 1;
+            diff.extend_from_slice(&[255, 0, 255, 255]);
+        }
+    }
+    let ratio = mismatched as f32 / (SIZE * SIZE) as f32;
+    if ratio <= MAX_MISMATCHED {
+        return Ok(());
+    }
+
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&out_dir)?;
+    save(&out_dir.join(format!("{name}.actual.png")), pixels)?;
+    save(&out_dir.join(format!("{name}.diff.png")), &diff)?;
+    Err(ggez::GameError::CustomError(format!(
+        "{mismatched} pixels differ, see {}",
+        out_dir.display()
+    )))
+}
+
+fn save(path: &Path, pixels: &[u8]) -> GameResult {
+    image::save_buffer(path, pixels, SIZE, SIZE, image::ColorType::Rgba8)
+        .map_err(|e| ggez::GameError::CustomError(e.to_string()))
+}
+
+fn camera() -> CameraBundle {
+    CameraBundle {
+        camera: Camera::new(
+            [0.0, 3.0, 6.0],
+            -90.0_f32.to_radians(),
+            -25.0_f32.to_radians(),
+        ),
+        ..Default::default()
+    }
+}
+
+/// Renders whatever `draw` queues to a fresh offscreen image and reads it back
+fn render(
+    ctx: &mut Context,
+    renderer: &mut Renderer3d,
+    draw: impl FnOnce(&mut Canvas3d),
+) -> GameResult<Vec<u8>> {
+    let image = graphics::Image::new_canvas_image(
+        ctx,
+        graphics::ImageFormat::Rgba8UnormSrgb,
+        SIZE,
+        SIZE,
+        1,
+    );
+    let mut camera = camera();
+    let mut canvas = Canvas3d::from_image(ctx, renderer, &mut camera, image);
+    draw(&mut canvas);
+    canvas.finish(ctx, Color::from_rgb(30, 30, 30))?;
+    canvas.read_pixels(ctx)
+}
+
+fn cube_mesh(texture: Option<graphics::Image>) -> Mesh3d {
+    Mesh3d {
+        texture,
+        ..Mesh3d::cube(2.0)
+    }
+}
+
+/// Tints the whole mesh `color`, hiding its texture
+fn solid(color: Color) -> DrawParam3d {
+    DrawParam3d::default().color(Color { a: 1.0, ..color })
+}
+
+/// An instance moved to `position`, tinted like [`DrawParam3d::color`]
+fn at(position: impl Into<Vec3>, color: Color) -> Instance3d {
+    Instance3d::new(Mat4::from_translation(position.into()), color)
+}
+
+/// Shows the texture without tinting it
+const UNTINTED: Color = Color::new(1.0, 1.0, 1.0, 0.0);
+
+fn cube_scene(ctx: &mut Context, renderer: &mut Renderer3d) -> GameResult<Vec<u8>> {
+    let cube = renderer.upload_mesh(ctx, &cube_mesh(None));
+    render(ctx, renderer, |canvas| {
+        canvas.draw(
+            &cube,
+            solid(Color::RED).rotation(Quat::from_rotation_y(0.5)),
+        );
+    })
+}
+
+fn depth_scene(ctx: &mut Context, renderer: &mut Renderer3d) -> GameResult<Vec<u8>> {
+    let cube = renderer.upload_mesh(ctx, &cube_mesh(None));
+    render(ctx, renderer, |canvas| {
+        // Queued front to back, so only the depth test keeps the red cube in front
+        canvas.draw_instances(
+            &cube,
+            &[
+                at([-0.5, 0.0, 1.0], Color::RED),
+                at([0.5, 0.0, -1.0], Color::BLUE),
+            ],
+        );
+    })
+}
+
+fn translucent_cube(ctx: &mut Context, renderer: &mut Renderer3d) -> MeshHandle {
+    let texture = graphics::Image::from_color(ctx, 1, 1, Some(Color::new(0.0, 1.0, 0.0, 0.5)));
+    renderer.upload_mesh(ctx, &cube_mesh(Some(texture)))
+}
+
+fn transparent_scene(ctx: &mut Context, renderer: &mut Renderer3d) -> GameResult<Vec<u8>> {
+    let cube = renderer.upload_mesh(ctx, &cube_mesh(None));
+    let glass = translucent_cube(ctx, renderer);
+    render(ctx, renderer, |canvas| {
+        canvas.set_blend_mode(BlendMode::ALPHA);
+        canvas.draw_instances(&glass, &[at([0.8, 0.0, 1.5], UNTINTED)]);
+        canvas.draw_instances(&glass, &[at([-0.8, 0.0, 0.5], UNTINTED)]);
+        canvas.set_blend_mode(BlendMode::REPLACE);
+        canvas.draw_instances(&cube, &[at([0.0, 0.0, -1.5], Color::RED)]);
+    })
+}
+
+fn oit_scene(ctx: &mut Context, renderer: &mut Renderer3d) -> GameResult<Vec<u8>> {
+    let cube = renderer.upload_mesh(ctx, &cube_mesh(None));
+    let glass = translucent_cube(ctx, renderer);
+    render(ctx, renderer, |canvas| {
+        canvas.set_oit(true);
+        canvas.draw_instances(&glass, &[at([0.8, 0.0, 1.5], UNTINTED)]);
+        canvas.draw_instances(&glass, &[at([-0.8, 0.0, 0.5], UNTINTED)]);
+        canvas.set_oit(false);
+        canvas.draw_instances(&cube, &[at([0.0, 0.0, -1.5], Color::RED)]);
+    })
+}
+
+fn msaa_scene(ctx: &mut Context, renderer: &mut Renderer3d) -> GameResult<Vec<u8>> {
+    let cube = renderer.upload_mesh(ctx, &cube_mesh(None));
+    render(ctx, renderer, |canvas| {
+        canvas.set_sample_count(4);
+        canvas.draw(
+            &cube,
+            solid(Color::BLUE).rotation(Quat::from_rotation_y(0.5)),
+        );
+    })
+}
+
+/// Whether the pixel at `x`, `y` is mostly `channel`
+fn dominated_by(pixels: &[u8], x: u32, y: u32, channel: usize) -> bool {
+    let i = ((y * SIZE + x) * 4) as usize;
+    let pixel = &pixels[i..i + 3];
+    (0..3).all(|other| other == channel || pixel[channel] > pixel[other].saturating_add(64))
+}
+
+/// Two canvases drawn into one target within a frame, each with its own camera and instances
+fn layers_scene(ctx: &mut Context, renderer: &mut Renderer3d) -> GameResult<Vec<u8>> {
+    let cube = renderer.upload_mesh(ctx, &Mesh3d::cube(1.0));
+    let image = graphics::Image::new_canvas_image(
+        ctx,
+        graphics::ImageFormat::Rgba8UnormSrgb,
+        SIZE,
+        SIZE,
+        1,
+    );
+    let looking_at = |x: f32| CameraBundle {
+        camera: Camera::new([x, 0.0, 6.0], -90.0_f32.to_radians(), 0.0),
+        ..Default::default()
+    };
+
+    ctx.gfx.begin_frame()?;
+    let mut camera = looking_at(0.0);
+    let mut canvas = Canvas3d::from_image(ctx, renderer, &mut camera, image.clone());
+    canvas.draw_instances(&cube, &[at(Vec3::ZERO, Color::RED)]);
+    canvas.finish(ctx, Color::from_rgb(30, 30, 30))?;
+    // Far off to the side, so it only lines up with the first camera if it is ignored
+    let mut camera = looking_at(20.0);
+    let mut canvas = Canvas3d::from_image(ctx, renderer, &mut camera, image.clone());
+    canvas.draw_instances(
+        &cube,
+        &[
+            at([18.0, 2.0, 0.0], Color::BLUE),
+            at([22.0, 2.0, 0.0], Color::BLUE),
+            at([20.0, -2.0, 0.0], Color::BLUE),
+        ],
+    );
+    canvas.finish(ctx, None)?;
+    if Renderer3d::read_pixels(ctx, &image).is_ok() {
+        return Err(ggez::GameError::CustomError(String::from(
+            "read pixels inside the frame that hasn't been submitted",
+        )));
+    }
+    ctx.gfx.end_frame()?;
+
+    let pixels = Renderer3d::read_pixels(ctx, &image)?;
+    // 2 units off center is about 30 pixels at this distance
+    let expected = [
+        (64, 64, 0, "red cube of the first canvas"),
+        (34, 34, 2, "top left cube of the second canvas"),
+        (94, 34, 2, "top right cube of the second canvas"),
+        (64, 94, 2, "bottom cube of the second canvas"),
+    ];
+    for (x, y, channel, what) in expected {
+        if !dominated_by(&pixels, x, y, channel) {
+            return Err(ggez::GameError::CustomError(format!(
+                "missing the {what} at {x}, {y}"
+            )));
+        }
+    }
+    Ok(pixels)
+}
@@ -0,0 +1,95 @@
+use ggez::GameError;
+use ggez_3d::formats::obj;
+
+const QUADS: &str = "
+# two quads sharing an edge
+mtllib scene.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 2 0 0
+v 2 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+usemtl red
+f 1/1/1 2/2/1 3/3/1 4/4/1
+usemtl blue
+f -5/1/1 -2/2/1 -1/3/1 -4/4/1
+";
+
+#[test]
+fn triangulates_and_groups_by_material() {
+    let data = obj::parse(QUADS).unwrap();
+    assert_eq!(data.material_libs, ["scene.mtl"]);
+    assert_eq!(data.groups.len(), 2);
+    assert_eq!(data.groups[0].material.as_deref(), Some("red"));
+    assert_eq!(data.groups[0].indices, [0, 1, 2, 0, 2, 3]);
+    assert_eq!(data.groups[0].vertices.len(), 4);
+    assert_eq!(data.groups[1].material.as_deref(), Some("blue"));
+    assert_eq!(data.groups[1].vertices[0].pos, [1.0, 0.0, 0.0]);
+    assert_eq!(data.groups[1].vertices[2].pos, [2.0, 1.0, 0.0]);
+    // v flipped so the top of the image is 0
+    assert_eq!(data.groups[0].vertices[0].tex_coord, [0.0, 1.0]);
+    assert_eq!(data.groups[0].vertices[0].normal, [0.0, 0.0, 1.0]);
+}
+
+#[test]
+fn shares_identical_vertices() {
+    let data = obj::parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nf 1 2 3\nf 3 2 4\n").unwrap();
+    assert_eq!(data.groups.len(), 1);
+    assert_eq!(data.groups[0].material, None);
+    assert_eq!(data.groups[0].vertices.len(), 4);
+    assert_eq!(data.groups[0].indices, [0, 1, 2, 2, 1, 3]);
+
+    // Same position with a different normal needs its own vertex
+    let data = obj::parse(
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nvn 0 0 -1\nf 1//1 2//1 3//1\nf 1//2 3//2 2//2\n",
+    )
+    .unwrap();
+    assert_eq!(data.groups[0].vertices.len(), 6);
+}
+
+fn error_message(source: &str) -> String {
+    match obj::parse(source) {
+        Err(GameError::ResourceLoadError(message)) => message,
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("parsed {source:?}"),
+    }
+}
+
+#[test]
+fn reports_line_numbers() {
+    assert_eq!(
+        error_message("v 0 0 0\nv 1 0 0\nf 1 2 3\n"),
+        "line 3: index 3 is out of range, there are 2"
+    );
+    assert_eq!(
+        error_message("v 0 0 0\n\nv 1 zero 0\n"),
+        "line 3: `zero` is not a number"
+    );
+    assert_eq!(
+        error_message("v 0 0 0\nv 1 0 0\nf 1 2\n"),
+        "line 3: face has 2 vertices"
+    );
+}
+
+#[test]
+fn parses_materials() {
+    let materials = obj::parse_mtl(
+        "newmtl red\nKd 1 0 0\nd 0.5\n\nnewmtl wood\nKd 0.8 0.8 0.8\nmap_Kd -s 2 2 1 textures/wood.png\n",
+    )
+    .unwrap();
+    assert_eq!(materials.len(), 2);
+    assert_eq!(materials[0].name, "red");
+    assert_eq!(materials[0].diffuse, [1.0, 0.0, 0.0]);
+    assert_eq!(materials[0].diffuse_texture, None);
+    assert_eq!(
+        materials[1].diffuse_texture.as_deref(),
+        Some("textures/wood.png")
+    );
+    assert!(obj::parse_mtl("Kd 1 1 1\n").is_err());
+}
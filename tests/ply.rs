@@ -0,0 +1,155 @@
+use ggez::GameError;
+use ggez_3d::formats::ply;
+
+const QUAD: &str = "ply
+format ascii 1.0
+comment a colored quad
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1 255 0 0
+1 0 0 0 0 1 0 255 0
+1 1 0 0 0 1 0 0 255
+0 1 0 0 0 1 255 255 255
+4 0 1 2 3
+";
+
+#[test]
+fn parses_ascii_faces() {
+    let mesh = ply::parse(QUAD.as_bytes()).unwrap();
+    assert_eq!(mesh.topology, wgpu::PrimitiveTopology::TriangleList);
+    assert_eq!(mesh.vertices.len(), 4);
+    assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+    assert_eq!(mesh.vertices[2].pos, [1.0, 1.0, 0.0]);
+    assert_eq!(mesh.vertices[2].normal, [0.0, 0.0, 1.0]);
+    assert_eq!(mesh.vertices[0].color, [1.0, 0.0, 0.0, 1.0]);
+    assert_eq!(mesh.vertices[2].color, [0.0, 0.0, 1.0, 1.0]);
+}
+
+#[test]
+fn parses_binary_point_clouds() {
+    let mut bytes = b"ply\nformat binary_little_endian 1.0\nelement vertex 2\n\
+property double x\nproperty double y\nproperty double z\n\
+property ushort intensity\nproperty float red\nproperty float green\nproperty float blue\n\
+end_header\n"
+        .to_vec();
+    for (position, color) in [
+        ([1.0f64, 2.0, 3.0], [0.5f32, 0.25, 1.0]),
+        ([-1.0, 0.0, 1.0], [0.0; 3]),
+    ] {
+        for value in position {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(7u16.to_le_bytes());
+        for value in color {
+            bytes.extend(value.to_le_bytes());
+        }
+    }
+    let mesh = ply::parse(&bytes).unwrap();
+    assert_eq!(mesh.topology, wgpu::PrimitiveTopology::PointList);
+    assert_eq!(mesh.indices, [0, 1]);
+    assert_eq!(mesh.vertices[0].pos, [1.0, 2.0, 3.0]);
+    assert_eq!(mesh.vertices[0].color, [0.5, 0.25, 1.0, 1.0]);
+    assert_eq!(mesh.vertices[1].pos, [-1.0, 0.0, 1.0]);
+
+    bytes.pop();
+    assert!(ply::parse(&bytes).is_err());
+}
+
+#[test]
+fn skips_other_properties_and_elements() {
+    let source = "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar int texcoord
+property list uchar uint vertex_index
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+0 0 0
+1 0 0
+0 1 0
+0 3 0 1 2
+0 1
+";
+    let mesh = ply::parse(source.as_bytes()).unwrap();
+    assert_eq!(mesh.vertices[1].pos, [1.0, 0.0, 0.0]);
+    assert_eq!(mesh.indices, [0, 1, 2]);
+}
+
+fn error_message(source: &str) -> String {
+    match ply::parse(source.as_bytes()) {
+        Err(GameError::ResourceLoadError(message)) => message,
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("parsed {source:?}"),
+    }
+}
+
+#[test]
+fn reports_errors() {
+    assert_eq!(
+        error_message(&QUAD.replace("4 0 1 2 3", "4 0 1 2 9")),
+        "index 9 is out of range, there are 4 vertices"
+    );
+    assert_eq!(
+        error_message(&QUAD.replace("1 1 0 0 0 1", "1 one 0 0 0 1")),
+        "line 19: `one` is not a number"
+    );
+    assert_eq!(
+        error_message(&QUAD.replace("ascii", "binary_big_endian")),
+        "line 2: unsupported format `binary_big_endian`"
+    );
+    assert_eq!(
+        error_message(&QUAD.replace("float x", "half x")),
+        "line 5: unknown type `half`"
+    );
+    assert_eq!(
+        error_message(&QUAD.replace("4 0 1 2 3", "4 0 1 -2 3")),
+        "face has the negative index -2"
+    );
+}
+
+#[test]
+fn doesnt_trust_element_counts() {
+    let mut bytes = b"ply\nformat binary_little_endian 1.0\nelement vertex 4000000000000\n\
+property float x\nproperty float y\nproperty float z\nend_header\n"
+        .to_vec();
+    bytes.extend([0u8; 12]);
+    // Fails before reserving the vertices
+    assert!(ply::parse(&bytes).is_err());
+
+    assert_eq!(
+        error_message(&QUAD.replace("vertex 4", "vertex 4000000000000")),
+        "the file only has room for 4 of the 4000000000000 vertex elements it claims"
+    );
+
+    // Rows without properties take up no space, there's no end of the file to run into
+    let empty = QUAD.replace(
+        "element face 1",
+        "element empty 4000000000000\nelement face 1",
+    );
+    assert_eq!(
+        error_message(&empty),
+        "the file only has room for 0 of the 4000000000000 empty elements it claims"
+    );
+    assert!(ply::parse(
+        QUAD.replace("element face 1", "element empty 0\nelement face 1")
+            .as_bytes()
+    )
+    .is_ok());
+}
@@ -0,0 +1,130 @@
+use ggez::glam::Vec3;
+use ggez_3d::prelude::*;
+use std::collections::HashMap;
+
+/// Checks indices are in range, normals are unit length, and every triangle is wound
+/// counter clockwise around the normals of its vertices
+fn check(name: &str, mesh: &Mesh3d) {
+    assert!(!mesh.indices.is_empty(), "{name} has no triangles");
+    assert_eq!(mesh.indices.len() % 3, 0, "{name} has a partial triangle");
+    for vertex in mesh.vertices.iter() {
+        let normal = Vec3::from(vertex.normal);
+        assert!(
+            (normal.length() - 1.0).abs() < 1e-4,
+            "{name} has a normal of length {}",
+            normal.length()
+        );
+        assert!(vertex.tex_coord.iter().all(|uv| uv.is_finite()));
+    }
+    for triangle in mesh.indices.chunks_exact(3) {
+        let vertices = triangle.iter().map(|&i| mesh.vertices[i as usize]);
+        let positions = vertices
+            .clone()
+            .map(|v| Vec3::from(v.pos))
+            .collect::<Vec<_>>();
+        let normal = vertices.map(|v| Vec3::from(v.normal)).sum::<Vec3>();
+        let face = (positions[1] - positions[0]).cross(positions[2] - positions[0]);
+        assert!(
+            face.dot(normal) > 0.0,
+            "{name} triangle {triangle:?} is wound against its normals"
+        );
+    }
+}
+
+/// Checks texture coordinates stay within the image
+fn check_uvs(name: &str, mesh: &Mesh3d) {
+    for vertex in mesh.vertices.iter() {
+        assert!(
+            vertex.tex_coord.iter().all(|uv| (0.0..=1.0).contains(uv)),
+            "{name} has uv {:?}",
+            vertex.tex_coord
+        );
+    }
+}
+
+/// Checks the surface is closed: every edge, found by the positions at its ends, is shared by
+/// exactly two triangles, which also rules out cracks and T-junctions along seams
+fn check_watertight(name: &str, mesh: &Mesh3d) {
+    let mut edges: HashMap<([u32; 3], [u32; 3]), u32> = HashMap::new();
+    for triangle in mesh.indices.chunks_exact(3) {
+        let corners = triangle
+            .iter()
+            .map(|&i| mesh.vertices[i as usize].pos.map(f32::to_bits));
+        let corners = corners.collect::<Vec<_>>();
+        for k in 0..3 {
+            let (a, b) = (corners[k], corners[(k + 1) % 3]);
+            *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+    for ((a, b), count) in edges {
+        assert_eq!(
+            count,
+            2,
+            "{name} edge from {:?} to {:?} is used by {count} triangles",
+            a.map(f32::from_bits),
+            b.map(f32::from_bits)
+        );
+    }
+}
+
+#[test]
+fn cube() {
+    let mesh = Mesh3d::cube(2.0);
+    check("cube", &mesh);
+    check_uvs("cube", &mesh);
+    assert_eq!(mesh.vertices.len(), 24);
+    assert_eq!(mesh.indices.len(), 36);
+    let aabb = mesh.to_aabb().unwrap();
+    assert_eq!(Vec3::from(aabb.half_extents), Vec3::ONE);
+}
+
+#[test]
+fn plane() {
+    let mesh = Mesh3d::plane(4.0, 3);
+    check("plane", &mesh);
+    check_uvs("plane", &mesh);
+    assert_eq!(mesh.vertices.len(), 25);
+    assert_eq!(mesh.indices.len(), 4 * 4 * 6);
+}
+
+#[test]
+fn spheres() {
+    let mesh = Mesh3d::uv_sphere(1.5, 16, 8);
+    check("uv_sphere", &mesh);
+    check_uvs("uv_sphere", &mesh);
+    for subdivisions in 0..4 {
+        let mesh = Mesh3d::icosphere(1.5, subdivisions);
+        check("icosphere", &mesh);
+        check_uvs("icosphere", &mesh);
+        check_watertight("icosphere", &mesh);
+        // No triangle stretches back across the whole texture
+        for triangle in mesh.indices.chunks_exact(3) {
+            let us = triangle
+                .iter()
+                .map(|&i| mesh.vertices[i as usize].tex_coord[0]);
+            let (min, max) = us.fold((1.0_f32, 0.0_f32), |(min, max), u| (min.min(u), max.max(u)));
+            assert!(
+                max - min <= 0.5,
+                "icosphere triangle {triangle:?} wraps around"
+            );
+        }
+        // Along with the pieces of triangles cut along the seam
+        assert!(mesh.indices.len() >= 20 * 4usize.pow(subdivisions) * 3);
+        for vertex in mesh.vertices.iter() {
+            assert!((Vec3::from(vertex.pos).length() - 1.5).abs() < 1e-4);
+        }
+    }
+}
+
+#[test]
+fn round_shapes() {
+    for (name, mesh) in [
+        ("cylinder", Mesh3d::cylinder(1.0, 2.0, 12)),
+        ("cone", Mesh3d::cone(1.0, 2.0, 12)),
+        ("capsule", Mesh3d::capsule(0.5, 1.0, 12, 4)),
+        ("torus", Mesh3d::torus(1.0, 0.25, 16, 8)),
+    ] {
+        check(name, &mesh);
+        check_uvs(name, &mesh);
+    }
+}
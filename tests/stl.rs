@@ -0,0 +1,80 @@
+use ggez::GameError;
+use ggez_3d::formats::stl;
+
+const TRIANGLE: &str = "solid part
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid part
+";
+
+/// Builds a binary STL, giving every triangle a zero normal like some exporters do
+fn binary(header: &[u8], triangles: &[[[f32; 3]; 3]]) -> Vec<u8> {
+    let mut bytes = header.to_vec();
+    bytes.resize(80, 0);
+    bytes.extend((triangles.len() as u32).to_le_bytes());
+    for triangle in triangles {
+        bytes.extend([0u8; 12]);
+        for value in triangle.iter().flatten() {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend([0u8; 2]);
+    }
+    bytes
+}
+
+#[test]
+fn parses_ascii() {
+    let mesh = stl::parse(TRIANGLE.as_bytes()).unwrap();
+    assert_eq!(mesh.indices, [0, 1, 2]);
+    assert_eq!(mesh.vertices[1].pos, [1.0, 0.0, 0.0]);
+    // Computed from the winding, not the zero normal in the file
+    assert!(mesh.vertices.iter().all(|v| v.normal == [0.0, 0.0, 1.0]));
+}
+
+#[test]
+fn parses_binary() {
+    let triangles = [
+        [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]],
+        [[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]],
+    ];
+    // Headers starting with `solid` are common and mustn't be taken for ASCII
+    let mesh = stl::parse(&binary(b"solid exported", &triangles)).unwrap();
+    assert_eq!(mesh.vertices.len(), 6);
+    assert_eq!(mesh.indices, [0, 1, 2, 3, 4, 5]);
+    assert_eq!(mesh.vertices[2].pos, [1.0, 0.0, 0.0]);
+    assert_eq!(mesh.vertices[0].normal, [0.0, 0.0, -1.0]);
+    assert_eq!(mesh.vertices[3].normal, [-1.0, 0.0, 0.0]);
+
+    let mut truncated = binary(b"", &triangles);
+    truncated.pop();
+    assert!(stl::parse(&truncated).is_err());
+}
+
+fn error_message(source: &str) -> String {
+    match stl::parse(source.as_bytes()) {
+        Err(GameError::ResourceLoadError(message)) => message,
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("parsed {source:?}"),
+    }
+}
+
+#[test]
+fn reports_line_numbers() {
+    assert_eq!(
+        error_message(&TRIANGLE.replace("vertex 1 0 0", "vertex 1 zero 0")),
+        "line 5: `zero` is not a number"
+    );
+    assert_eq!(
+        error_message(&TRIANGLE.replace("      vertex 0 1 0\n", "")),
+        "line 6: facet has 2 vertices"
+    );
+    assert_eq!(
+        error_message("solid\n  vertex 0 0 0\n"),
+        "line 2: unexpected `vertex`"
+    );
+}